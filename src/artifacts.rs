@@ -0,0 +1,185 @@
+/*!
+Declarative description of artifacts
+
+Projects which depend on several archives may describe them in TOML file
+(like `fetch.toml`) instead of doing it in code of build script.
+
+```toml
+[[artifact]]
+url = "https://example.com/libfoo-1.0.tar.gz"
+sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+dest = "libfoo"
+strip_components = 1
+include = ["*.h", "*.a"]
+
+[[artifact]]
+url = "https://example.com/data.bin"
+dest = "data.bin"
+save = true
+```
+
+```no_run
+use fetch_unroll::artifacts::Artifacts;
+
+let out_dir = std::env::var("OUT_DIR").unwrap();
+
+Artifacts::load("fetch.toml").unwrap().to(out_dir).unwrap();
+```
+ */
+
+use std::{
+    fs::read_to_string,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    rerun_if_changed, unroll::matches_patterns, Error, Fetch, FileContext, IoError, IoErrorKind,
+    Result, Summary, Unroll,
+};
+
+/// Set of artifacts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Artifacts {
+    /// Artifacts in order of processing
+    #[serde(default, rename = "artifact")]
+    pub artifacts: Vec<Artifact>,
+}
+
+/// Single artifact
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Artifact {
+    /// Url to fetch from
+    pub url: String,
+
+    /// Expected SHA-256 hash of fetched data in hex
+    #[serde(default)]
+    pub sha256: Option<String>,
+
+    /// Destination path
+    ///
+    /// Relative paths are resolved against the directory passed to [`Artifacts::to`].
+    pub dest: PathBuf,
+
+    /// See [`Unroll::strip_components`]
+    #[serde(default)]
+    pub strip_components: usize,
+
+    /// See [`Unroll::strip_toplevel`]
+    #[serde(default)]
+    pub strip_toplevel: bool,
+
+    /// Extract only entries matching any of patterns
+    ///
+    /// Patterns are matched the same way as [`Unroll::exec_pattern`] does.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Skip entries matching any of patterns
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Save fetched file as is instead of unrolling
+    #[serde(default)]
+    pub save: bool,
+}
+
+impl FromStr for Artifacts {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        toml::from_str(source).map_err(|error| {
+            Error::Decode(IoError::new(
+                IoErrorKind::InvalidData,
+                format!("Invalid artifacts: {error}"),
+            ))
+        })
+    }
+}
+
+impl Artifacts {
+    /// Load artifacts from TOML file
+    ///
+    /// # Errors
+    /// - File cannot be read
+    /// - Contents of file is not valid
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        rerun_if_changed(path);
+        read_to_string(path).context("read file", path)?.parse()
+    }
+
+    /// Fetch all artifacts into specified directory
+    ///
+    /// Returns summaries of operations in order of artifacts.
+    ///
+    /// # Errors
+    /// - Processing of some artifact failed (the rest are not processed)
+    pub fn to<D>(&self, dir: D) -> Result<Vec<Summary>>
+    where
+        D: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        self.artifacts
+            .iter()
+            .map(|artifact| artifact.to(dir))
+            .collect()
+    }
+}
+
+impl Artifact {
+    /// Fetch artifact into specified directory
+    ///
+    /// # Errors
+    /// - Fetching, verification or writing failed
+    pub fn to<D>(&self, dir: D) -> Result<Summary>
+    where
+        D: AsRef<Path>,
+    {
+        let dest = dir.as_ref().join(&self.dest);
+
+        let Some(sha256) = &self.sha256 else {
+            return self.fetch(Fetch::from(&self.url), &dest);
+        };
+        self.fetch(Fetch::verified(&self.url, sha256.as_str()), &dest)
+    }
+
+    fn fetch<R>(&self, fetch: Fetch<R>, dest: &Path) -> Result<Summary>
+    where
+        R: Read,
+    {
+        if self.save {
+            fetch.save().to(dest)
+        } else {
+            self.unroll(fetch.unroll(), dest)
+        }
+    }
+
+    fn unroll<R>(&self, unroll: Unroll<R>, dest: &Path) -> Result<Summary>
+    where
+        R: Read,
+    {
+        let mut unroll = unroll
+            .strip_components(self.strip_components)
+            .strip_toplevel(self.strip_toplevel);
+
+        if !self.include.is_empty() || !self.exclude.is_empty() {
+            let (include, exclude) = (self.include.clone(), self.exclude.clone());
+            unroll = unroll.map_path(move |path| {
+                ((include.is_empty() || matches_patterns(&include, path))
+                    && !matches_patterns(&exclude, path))
+                .then(|| path.into())
+            });
+        }
+
+        Ok(unroll.to(dest)?.summary)
+    }
+}
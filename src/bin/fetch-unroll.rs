@@ -0,0 +1,184 @@
+//! Command line interface to fetching and unrolling of archives
+//!
+//! Useful in Makefiles, Dockerfiles and for quick checks of options used in build scripts.
+
+use std::{
+    convert::TryFrom,
+    env::args,
+    error::Error,
+    io::{stdout, Read},
+    path::PathBuf,
+    process::exit,
+    time::Duration,
+};
+
+use fetch_unroll::{set_observer, Backoff, Fetch, FetchConfig, JsonObserver, Save, Unroll};
+
+const USAGE: &str = "\
+Usage: fetch-unroll [OPTIONS] URL DEST
+
+Fetch .tar.gz archive from URL and unroll it into DEST directory.
+
+Options:
+    --strip-components N  Strip N leading components from paths of entries
+    --strip-toplevel      Strip single top-level directory
+    --keep-dest           Do not cleanup destination directory before extraction
+    --save                Save fetched file to DEST instead of unrolling
+    --sha256 HEX          Verify SHA-256 hash of fetched data
+    --retries N           Retry failed requests N times (default: $FETCH_UNROLL_RETRIES or 0)
+    --json                Print events as JSON lines to stdout
+    -h, --help            Print this help
+    -V, --version         Print version
+";
+
+#[derive(Default)]
+struct Args {
+    url: String,
+    dest: PathBuf,
+    strip_components: usize,
+    strip_toplevel: bool,
+    keep_dest: bool,
+    save: bool,
+    sha256: Option<String>,
+    retries: Option<usize>,
+    json: bool,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut positional = Vec::new();
+        let mut args = args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("Missing value of `{name}`"))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("fetch-unroll {}", env!("CARGO_PKG_VERSION"));
+                    exit(0);
+                }
+                "--strip-components" => {
+                    parsed.strip_components = value(&arg)?
+                        .parse()
+                        .map_err(|error| format!("Invalid value of `{arg}`: {error}"))?;
+                }
+                "--strip-toplevel" => parsed.strip_toplevel = true,
+                "--keep-dest" => parsed.keep_dest = true,
+                "--save" => parsed.save = true,
+                "--json" => parsed.json = true,
+                "--sha256" => parsed.sha256 = Some(value(&arg)?.to_lowercase()),
+                "--retries" => {
+                    parsed.retries = Some(
+                        value(&arg)?
+                            .parse()
+                            .map_err(|error| format!("Invalid value of `{arg}`: {error}"))?,
+                    );
+                }
+                _ if arg.starts_with('-') => return Err(format!("Unknown option `{arg}`")),
+                _ => positional.push(arg),
+            }
+        }
+
+        let [url, dest] = <[String; 2]>::try_from(positional)
+            .map_err(|_| "Expected URL and DEST arguments".to_owned())?;
+        parsed.url = url;
+        parsed.dest = dest.into();
+
+        Ok(parsed)
+    }
+}
+
+/// Error message with all its causes
+fn describe(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+fn unroll<R: Read>(unroll: Unroll<R>, args: &Args) -> Result<(), String> {
+    let manifest = unroll
+        .strip_components(args.strip_components)
+        .strip_toplevel(args.strip_toplevel)
+        .cleanup_dest_dir(!args.keep_dest)
+        .threaded_decode(true)
+        .show_progress()
+        .to(&args.dest)
+        .map_err(|error| describe(&error))?;
+
+    eprintln!(
+        "Unrolled {} entries ({} bytes) to `{}` in {:.2?}",
+        manifest.summary.entries,
+        manifest.summary.bytes_written,
+        args.dest.display(),
+        manifest.summary.elapsed,
+    );
+    Ok(())
+}
+
+fn save<R: Read>(save: Save<R>, args: &Args) -> Result<(), String> {
+    let summary = save
+        .show_progress()
+        .to(&args.dest)
+        .map_err(|error| describe(&error))?;
+
+    eprintln!(
+        "Saved {} bytes to `{}` in {:.2?}",
+        summary.bytes_written,
+        args.dest.display(),
+        summary.elapsed,
+    );
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let mut config = FetchConfig::new();
+    if let Some(retries) = args.retries {
+        config = config.retry(Backoff {
+            retries,
+            delay: Duration::from_secs(1),
+        });
+    }
+    // Network reads and decompression run in parallel with writing
+    let fetch = Fetch::with_config(args.url.clone(), &config).read_ahead();
+
+    match &args.sha256 {
+        Some(expected) => process(fetch.sha256(expected.as_str()), args),
+        None => process(fetch, args),
+    }
+}
+
+fn process<R: Read>(fetch: Fetch<R>, args: &Args) -> Result<(), String> {
+    if args.save {
+        save(fetch.save(), args)
+    } else {
+        unroll(fetch.unroll(), args)
+    }
+}
+
+fn main() {
+    let args = Args::parse().unwrap_or_else(|error| {
+        eprintln!("fetch-unroll: {error}");
+        eprintln!("Try `fetch-unroll --help` for more information.");
+        exit(2);
+    });
+
+    if args.json {
+        set_observer(JsonObserver::new(stdout()));
+    }
+
+    if let Err(error) = run(&args) {
+        eprintln!("fetch-unroll: {error}");
+        exit(1);
+    }
+}
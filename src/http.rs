@@ -0,0 +1,782 @@
+/*!
+HTTP requests and resolving of fetched resources
+
+Besides plain requests there are lookups of GitHub release assets
+and Git LFS objects, which need a bit of JSON parsing.
+ */
+
+use std::{
+    io::{Error as IoError, Read, Result as IoResult},
+    time::{Duration, SystemTime},
+};
+
+use sha2::{Digest as _, Sha256};
+use ureq::{
+    Agent as HttpAgent, Error as HttpError, Request as HttpRequest, Response as HttpResponse,
+};
+use url::Url;
+
+use crate::{
+    cargo_warning, is_classified, json_str, known_failure, observe, Error, ErrorChain, ErrorKind,
+    Event, FetchConfig, FetchFailed, Metadata, Result, ResumeFrom, Verified,
+};
+
+/// Response of HTTP request
+pub struct HttpReply {
+    pub(crate) status: u16,
+    /// Final url after redirects
+    pub(crate) url: String,
+    /// Headers with lowercase names
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) reader: Box<dyn Read + Send + Sync>,
+}
+
+impl HttpReply {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn into_string(mut self) -> Result<String> {
+        let mut string = String::new();
+        self.reader.read_to_string(&mut string)?;
+        Ok(string)
+    }
+}
+
+impl From<HttpResponse> for HttpReply {
+    fn from(response: HttpResponse) -> Self {
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.into();
+                Some((name, value))
+            })
+            .collect();
+        let url = String::from(response.get_url());
+        Self {
+            status: response.status(),
+            url: url.clone(),
+            headers,
+            reader: Box::new(BodyReader {
+                url,
+                inner: response.into_reader(),
+            }),
+        }
+    }
+}
+
+/// Body of HTTP response which reports read failures with its url
+struct BodyReader<R> {
+    url: String,
+    inner: R,
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.inner.read(buf).map_err(|source| {
+            if is_classified(&source) {
+                return source;
+            }
+            IoError::other(FetchFailed(Error::Body {
+                url: self.url.clone(),
+                source,
+            }))
+        })
+    }
+}
+
+/// Send request with body (nothing is sent when it's empty)
+///
+/// All requests are made here, so the responses registered for testing are served instead of them.
+/// Unsuccessful status is an error, except 416 for request of range
+/// (it starts at the end so the data may be already complete).
+pub fn http_send(request: &HttpRequest, body: &str, config: &FetchConfig) -> Result<HttpReply> {
+    #[cfg(feature = "testing")]
+    if let Some(response) = crate::testing::respond(request) {
+        return response;
+    }
+
+    if config.offline {
+        return Err(Error::Offline {
+            url: request.url().into(),
+        });
+    }
+
+    let response = if body.is_empty() {
+        request.clone().call()
+    } else {
+        request.clone().send_string(body)
+    };
+    match response {
+        Ok(response) => Ok(response.into()),
+        Err(HttpError::Status(416, response)) if request.header("Range").is_some() => {
+            Ok(response.into())
+        }
+        Err(error) => Err(Error::http(request.url(), error)),
+    }
+}
+
+/// Fetch resource or the rest of it when `resume` is set
+///
+/// The whole resource is requested again when partial response does not continue
+/// the same resource. The `headers` are added to request (like ones required by Git LFS server).
+pub fn http_fetch(
+    agent: &HttpAgent,
+    url: &str,
+    config: &FetchConfig,
+    resume: Option<&ResumeFrom>,
+    headers: &[(String, String)],
+) -> Result<(Box<dyn Read + Send + Sync>, Metadata)> {
+    log_record!(info, "Fetching `{url}`");
+    trace_span!("fetch", url, status = tracing::field::Empty);
+    observe(&Event::FetchStarted { url });
+
+    // Private release assets are downloaded through API
+    let asset_request = github_asset_request(agent, url, config)?;
+    let is_asset = asset_request.is_some();
+    let mut request = asset_request.unwrap_or_else(|| agent.get(url));
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    if let Some(resume) = resume {
+        log_record!(
+            info,
+            "Resuming fetching of `{url}` from {} bytes",
+            resume.offset
+        );
+        request = request
+            .set("Range", &format!("bytes={}-", resume.offset))
+            .set("If-Range", &resume.validator);
+    }
+
+    let mut attempt = 0;
+    let response = loop {
+        match http_send(&request, "", config) {
+            Ok(response) => break response,
+            Err(error) => {
+                attempt += 1;
+                let Some(delay) = config.retry.retry(&error, attempt) else {
+                    return Err(error);
+                };
+                log_record!(warn, "Retrying after {delay:?}: {}", ErrorChain(&error));
+                cargo_warning(format_args!(
+                    "Retrying `{url}` after {delay:?} (attempt {attempt}): {}",
+                    ErrorChain(&error)
+                ));
+                observe(&Event::Retry {
+                    url,
+                    attempt,
+                    delay,
+                    error: &error,
+                });
+                std::thread::sleep(delay);
+            }
+        }
+    };
+
+    trace_record!("status", response.status);
+    if response.url != url {
+        observe(&Event::Redirected {
+            url,
+            location: &response.url,
+        });
+    }
+    log_record!(
+        debug,
+        "Fetched `{}` with status {}",
+        response.url,
+        response.status
+    );
+
+    let offset = match resume {
+        Some(resume) if matches!(response.status, 206 | 416) => {
+            let Some(offset) = resumed_offset(&response, resume) else {
+                log_record!(warn, "Partial response does not continue `{url}`");
+                return http_fetch(agent, url, config, None, headers);
+            };
+            offset
+        }
+        _ => 0,
+    };
+
+    let metadata = response_metadata(&response, url, is_asset, offset);
+
+    if response.status == 416 {
+        return Ok((Box::new(std::io::empty()), metadata));
+    }
+    let reader = response.reader;
+    if offset == 0
+        && config.resolve_lfs
+        && metadata
+            .content_length
+            .is_some_and(|length| length <= LFS_POINTER_MAX_SIZE)
+    {
+        // Only raw files of repositories with known LFS server are sniffed
+        if let Some(endpoint) = lfs_endpoint(&response.url) {
+            return resolve_lfs_pointer(agent, config, reader, metadata, &endpoint);
+        }
+    }
+    Ok((reader, metadata))
+}
+
+/// Get metadata of fetched resource from response headers
+fn response_metadata(response: &HttpReply, url: &str, is_asset: bool, offset: u64) -> Metadata {
+    Metadata {
+        last_modified: response.header("Last-Modified").and_then(parse_http_date),
+        file_name: response
+            .header("Content-Disposition")
+            .and_then(disposition_file_name)
+            // Asset is served from storage under meaningless name
+            .or_else(|| url_file_name(if is_asset { url } else { &response.url })),
+        url: Some(response.url.clone()),
+        content_length: if response.status == 416 {
+            Some(offset)
+        } else {
+            response
+                .header("Content-Length")
+                .and_then(|length| length.parse::<u64>().ok())
+                .map(|length| offset + length)
+        },
+        offset,
+        validator: response
+            .header("ETag")
+            .or_else(|| response.header("Last-Modified"))
+            .map(Into::into),
+        content_type: response.header("Content-Type").map(Into::into),
+        source_path: None,
+    }
+}
+
+/// Get offset of partial response when it continues the data of the same resource
+///
+/// The `Content-Range` should start at the offset of partial data (or report its length
+/// when nothing is left) and validator should match one of partial data.
+fn resumed_offset(response: &HttpReply, resume: &ResumeFrom) -> Option<u64> {
+    let validator = response
+        .header("ETag")
+        .or_else(|| response.header("Last-Modified"));
+    let (range, length) = response
+        .header("Content-Range")?
+        .strip_prefix("bytes ")?
+        .split_once('/')?;
+
+    let valid = match response.status {
+        206 => {
+            validator == Some(resume.validator.as_str())
+                && range.split_once('-')?.0.trim().parse::<u64>().ok()? == resume.offset
+        }
+        // Validator may be omitted here
+        _ => {
+            validator.is_none_or(|validator| validator == resume.validator)
+                && range == "*"
+                && length.trim().parse::<u64>().ok()? == resume.offset
+        }
+    };
+    valid.then_some(resume.offset)
+}
+
+/// Make request of private GitHub release asset using API
+///
+/// Returns `None` when token is not set or url is not a release download url.
+fn github_asset_request(
+    agent: &HttpAgent,
+    url: &str,
+    config: &FetchConfig,
+) -> Result<Option<HttpRequest>> {
+    let Some(token) = &config.github_token else {
+        return Ok(None);
+    };
+    let api = config.github_api.trim_end_matches('/');
+    let Some((owner, repo, tag, name)) = github_release_asset(url, api) else {
+        return Ok(None);
+    };
+
+    log_record!(
+        debug,
+        "Looking up asset `{name}` of `{owner}/{repo}` release `{tag}`"
+    );
+    let release = github_release(agent, config, &owner, &repo, &tag)?;
+    let asset_url = release
+        .get("assets")
+        .map_or(&[][..], Json::as_array)
+        .iter()
+        .find(|asset| asset.get("name").and_then(Json::as_str) == Some(name.as_str()))
+        .and_then(|asset| asset.get("url")?.as_str())
+        // Token should not be sent anywhere else
+        .filter(|asset_url| is_api_url(asset_url, api));
+    let Some(asset_url) = asset_url else {
+        return Err(Error::Status {
+            url: url.into(),
+            code: 404,
+        });
+    };
+
+    Ok(Some(
+        agent
+            .get(asset_url)
+            .set("Accept", "application/octet-stream")
+            .set("Authorization", &format!("Bearer {token}")),
+    ))
+}
+
+/// Request release of GitHub repository using API
+///
+/// The latest release is requested when tag is empty.
+pub fn github_release(
+    agent: &HttpAgent,
+    config: &FetchConfig,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+) -> Result<Json> {
+    let api = config.github_api.trim_end_matches('/');
+    let url = if tag.is_empty() {
+        format!("{api}/repos/{owner}/{repo}/releases/latest")
+    } else {
+        format!("{api}/repos/{owner}/{repo}/releases/tags/{tag}")
+    };
+
+    let mut request = agent.get(&url).set("Accept", "application/vnd.github+json");
+    if let Some(token) = &config.github_token {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    let release = http_send(&request, "", config)?.into_string()?;
+
+    Ok(Json::parse(&release)
+        .ok_or_else(|| known_failure(ErrorKind::Network, format!("Invalid response of `{url}`")))?)
+}
+
+/// Check that url belongs to API at specified base url
+///
+/// Scheme, host and port should match exactly and path should be inside of base path.
+pub fn is_api_url(url: &str, api: &str) -> bool {
+    let (Ok(url), Ok(api)) = (Url::parse(url), Url::parse(api)) else {
+        return false;
+    };
+    url.scheme() == api.scheme()
+        && url.host() == api.host()
+        && url.port_or_known_default() == api.port_or_known_default()
+        && url
+            .path()
+            .strip_prefix(api.path().trim_end_matches('/'))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Split GitHub release download url into owner, repository, tag and asset name
+pub fn github_release_asset(url: &str, api: &str) -> Option<(String, String, String, String)> {
+    let url = Url::parse(url).ok()?;
+    let api = Url::parse(api).ok()?;
+    let host = match api.host_str()? {
+        "api.github.com" => "github.com",
+        host => host,
+    };
+    if url.host_str()? != host || url.port_or_known_default() != api.port_or_known_default() {
+        return None;
+    }
+
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    let [owner, repo, "releases", "download", tag, name] = segments.as_slice() else {
+        return None;
+    };
+    Some((
+        (*owner).into(),
+        (*repo).into(),
+        (*tag).into(),
+        percent_decode(name),
+    ))
+}
+
+/// Maximum size of Git LFS pointer file
+const LFS_POINTER_MAX_SIZE: u64 = 1024;
+
+/// Media type of Git LFS API
+const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// Fetch Git LFS object when data is a pointer to it
+///
+/// Other data is passed as is.
+fn resolve_lfs_pointer(
+    agent: &HttpAgent,
+    config: &FetchConfig,
+    mut reader: Box<dyn Read + Send + Sync>,
+    metadata: Metadata,
+    endpoint: &str,
+) -> Result<(Box<dyn Read + Send + Sync>, Metadata)> {
+    let mut head = Vec::new();
+    reader
+        .by_ref()
+        .take(LFS_POINTER_MAX_SIZE)
+        .read_to_end(&mut head)?;
+
+    let Some((oid, size)) = parse_lfs_pointer(&head) else {
+        return Ok((Box::new(std::io::Cursor::new(head).chain(reader)), metadata));
+    };
+
+    log_record!(info, "Resolving Git LFS object {oid} using `{endpoint}`");
+    let (href, headers) = lfs_object_url(agent, config, endpoint, oid, size)?;
+    let (object, object_metadata) = http_fetch(agent, &href, config, None, &headers)?;
+
+    Ok((
+        Box::new(Verified {
+            inner: object,
+            expected: oid.into(),
+            hasher: Some(Sha256::new()),
+        }),
+        // Object url has no meaningful name
+        Metadata {
+            file_name: metadata.file_name,
+            last_modified: metadata.last_modified,
+            ..object_metadata
+        },
+    ))
+}
+
+/// Parse Git LFS pointer file to get hash and size of object
+pub fn parse_lfs_pointer(data: &[u8]) -> Option<(&str, u64)> {
+    let mut lines = std::str::from_utf8(data).ok()?.lines();
+    if lines.next()? != "version https://git-lfs.github.com/spec/v1" {
+        return None;
+    }
+
+    let mut oid = None;
+    let mut size = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("oid sha256:") {
+            oid = Some(value);
+        } else if let Some(value) = line.strip_prefix("size ") {
+            size = value.parse().ok();
+        }
+    }
+
+    let oid =
+        oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|byte| byte.is_ascii_hexdigit()))?;
+    Some((oid, size?))
+}
+
+/// Get url of Git LFS server for raw file url of repository
+pub fn lfs_endpoint(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    if parsed.host_str() == Some("raw.githubusercontent.com") {
+        let mut segments = parsed.path_segments()?;
+        let (owner, repo) = (segments.next()?, segments.next()?);
+        return Some(format!("https://github.com/{owner}/{repo}.git/info/lfs"));
+    }
+    // GitLab raw urls like `https://gitlab.com/group/project/-/raw/main/file`
+    let (repo, _) = url.split_once("/-/raw/")?;
+    Some(format!("{repo}.git/info/lfs"))
+}
+
+/// Request download url of Git LFS object using batch API
+///
+/// Returns the url with headers which should be sent with request of object.
+fn lfs_object_url(
+    agent: &HttpAgent,
+    config: &FetchConfig,
+    endpoint: &str,
+    oid: &str,
+    size: u64,
+) -> Result<(String, Vec<(String, String)>)> {
+    let url = format!("{endpoint}/objects/batch");
+    let request = format!(
+        r#"{{"operation":"download","transfers":["basic"],"objects":[{{"oid":{},"size":{size}}}]}}"#,
+        json_str(oid)
+    );
+    let request_body = request;
+    let request = agent
+        .post(&url)
+        .set("Accept", LFS_MEDIA_TYPE)
+        .set("Content-Type", LFS_MEDIA_TYPE);
+    let response = http_send(&request, &request_body, config)?.into_string()?;
+
+    let response = Json::parse(&response);
+    let object = response
+        .as_ref()
+        .and_then(|response| response.get("objects")?.as_array().first());
+    let download = object.and_then(|object| object.get("actions")?.get("download"));
+    let href = download.and_then(|download| download.get("href")?.as_str());
+    let headers = download
+        .and_then(|download| download.get("header"))
+        .map_or(&[][..], Json::as_object)
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.into())))
+        .collect();
+    href.map(|href| (href.into(), headers)).ok_or_else(|| {
+        let message = object
+            .and_then(|object| object.get("error")?.get("message")?.as_str())
+            .unwrap_or("invalid response");
+        known_failure(
+            ErrorKind::Network,
+            format!("Git LFS object {oid} is not available: {message}"),
+        )
+        .into()
+    })
+}
+
+/// Parsed JSON value
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Self>),
+    Object(Vec<(String, Self)>),
+}
+
+type JsonChars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+impl Json {
+    /// Maximum nesting of values
+    const MAX_DEPTH: usize = 64;
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut chars = text.chars().peekable();
+        let value = Self::parse_value(&mut chars, 0)?;
+        Self::skip_spaces(&mut chars);
+        chars.next().is_none().then_some(value)
+    }
+
+    /// Get field of object
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Self] {
+        match self {
+            Self::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn as_object(&self) -> &[(String, Self)] {
+        match self {
+            Self::Object(fields) => fields,
+            _ => &[],
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn skip_spaces(chars: &mut JsonChars<'_>) {
+        while chars.next_if(char::is_ascii_whitespace).is_some() {}
+    }
+
+    fn parse_value(chars: &mut JsonChars<'_>, depth: usize) -> Option<Self> {
+        if depth > Self::MAX_DEPTH {
+            return None;
+        }
+        Self::skip_spaces(chars);
+        Some(match *chars.peek()? {
+            '{' => {
+                chars.next();
+                let mut fields = Vec::new();
+                Self::skip_spaces(chars);
+                if chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        Self::skip_spaces(chars);
+                        let key = Self::parse_string(chars)?;
+                        Self::skip_spaces(chars);
+                        chars.next_if_eq(&':')?;
+                        fields.push((key, Self::parse_value(chars, depth + 1)?));
+                        Self::skip_spaces(chars);
+                        match chars.next()? {
+                            ',' => {}
+                            '}' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Self::Object(fields)
+            }
+            '[' => {
+                chars.next();
+                let mut items = Vec::new();
+                Self::skip_spaces(chars);
+                if chars.next_if_eq(&']').is_none() {
+                    loop {
+                        items.push(Self::parse_value(chars, depth + 1)?);
+                        Self::skip_spaces(chars);
+                        match chars.next()? {
+                            ',' => {}
+                            ']' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Self::Array(items)
+            }
+            '"' => Self::String(Self::parse_string(chars)?),
+            _ => {
+                let mut token = String::new();
+                while let Some(char) = chars
+                    .next_if(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '+' | '.'))
+                {
+                    token.push(char);
+                }
+                match token.as_str() {
+                    "null" => Self::Null,
+                    "true" => Self::Bool(true),
+                    "false" => Self::Bool(false),
+                    _ => Self::Number(token.parse().ok()?),
+                }
+            }
+        })
+    }
+
+    fn parse_string(chars: &mut JsonChars<'_>) -> Option<String> {
+        chars.next_if_eq(&'"')?;
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => return Some(value),
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    'b' => value.push('\u{8}'),
+                    'f' => value.push('\u{c}'),
+                    'u' => {
+                        let mut code = Self::parse_code_unit(chars)?;
+                        // Characters outside of basic plane are escaped as surrogate pairs
+                        if (0xD800..0xDC00).contains(&code) {
+                            chars.next_if_eq(&'\\')?;
+                            chars.next_if_eq(&'u')?;
+                            let low = Self::parse_code_unit(chars)?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return None;
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        value.push(char::from_u32(code)?);
+                    }
+                    char => value.push(char),
+                },
+                char => value.push(char),
+            }
+        }
+    }
+
+    /// Parse UTF-16 code unit of `\uXXXX` escape
+    fn parse_code_unit(chars: &mut JsonChars<'_>) -> Option<u32> {
+        let code = chars.by_ref().take(4).collect::<String>();
+        if code.len() != 4 || !code.chars().all(|char| char.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(&code, 16).ok()
+    }
+}
+
+/// Extract file name from `Content-Disposition` header
+///
+/// The extended `filename*` parameter is preferred when present.
+pub fn disposition_file_name(header: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for param in header.split(';').skip(1) {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename" => plain = Some(value.trim_matches('"').into()),
+            // Like `UTF-8''file%20name.tar.gz`
+            "filename*" => extended = value.splitn(3, '\'').nth(2).map(percent_decode),
+            _ => {}
+        }
+    }
+
+    extended.or(plain).as_deref().and_then(safe_file_name)
+}
+
+/// Extract file name from the last segment of URL path
+pub fn url_file_name(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    let (_, path) = url.split_once("://")?;
+    let (_, path) = path.split_once('/')?;
+    safe_file_name(&percent_decode(path.rsplit('/').next()?))
+}
+
+/// Keep only the last component of file name to avoid writing outside of directory
+fn safe_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.into())
+    }
+}
+
+/// Decode percent-encoded string
+fn percent_decode(string: &str) -> String {
+    let bytes = string.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let byte = match bytes[index] {
+            b'%' => string
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        if let Some(byte) = byte {
+            decoded.push(byte);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into()
+}
+
+/// Parse HTTP date in preferred format (like `Sun, 06 Nov 1994 08:49:37 GMT`)
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let mut parts = date.split_ascii_whitespace().skip(1);
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(str::parse::<u64>);
+    let (hour, min, sec) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if parts.next()? != "GMT" || year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since epoch for proleptic Gregorian calendar
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    Some(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + min * 60 + sec),
+    )
+}
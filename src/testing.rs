@@ -0,0 +1,147 @@
+/*!
+In-memory HTTP backend for hermetic tests
+
+Responses registered here are served instead of making real requests
+(including the ones made to APIs of GitHub, Git LFS and Conan),
+so crates which wrap this one can test their build logic without network.
+
+```
+use fetch_unroll::{testing, Fetch};
+
+testing::mock("https://example.com/data.txt", "data");
+testing::mock_status("https://example.com/missing.txt", 404);
+
+let data = Fetch::from("https://example.com/data.txt").bytes().unwrap();
+assert_eq!(data, b"data");
+
+let error = Fetch::from("https://example.com/missing.txt").bytes().unwrap_err();
+assert_eq!(error.status(), Some(404));
+```
+ */
+
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    sync::{Mutex, PoisonError},
+};
+
+use sha2::{Digest as _, Sha256};
+use ureq::Request;
+use url::Url;
+
+use crate::{http::HttpReply, to_hex, Error, Result};
+
+/// Registered response
+#[derive(Clone)]
+enum Response {
+    Body(Vec<u8>),
+    Status(u16),
+}
+
+/// Registered responses by normalized url
+static RESPONSES: Mutex<BTreeMap<String, Response>> = Mutex::new(BTreeMap::new());
+
+/// Normalize url the same way as it does when fetching
+fn normalize(url: &str) -> String {
+    Url::parse(url).map_or_else(|_| url.into(), String::from)
+}
+
+fn register(url: &str, response: Response) {
+    RESPONSES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(normalize(url), response);
+}
+
+/// Serve data for url
+pub fn mock<U, B>(url: U, body: B)
+where
+    U: AsRef<str>,
+    B: Into<Vec<u8>>,
+{
+    register(url.as_ref(), Response::Body(body.into()));
+}
+
+/// Respond with unsuccessful status for url
+pub fn mock_status<U>(url: U, code: u16)
+where
+    U: AsRef<str>,
+{
+    register(url.as_ref(), Response::Status(code));
+}
+
+/// Remove registered response for url
+///
+/// Real request will be made for it again.
+pub fn unmock<U>(url: U)
+where
+    U: AsRef<str>,
+{
+    RESPONSES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&normalize(url.as_ref()));
+}
+
+/// Get registered response for request
+///
+/// Body is served with strong `ETag` derived from its content,
+/// so the range requests made when resuming are answered like a real server does.
+pub(crate) fn respond(request: &Request) -> Option<Result<HttpReply>> {
+    let url = normalize(request.url());
+    let response = RESPONSES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&url)
+        .cloned()?;
+
+    Some(match response {
+        Response::Body(body) => Ok(reply(request, url, body)),
+        Response::Status(code) => Err(Error::Status { url, code }),
+    })
+}
+
+fn reply(request: &Request, url: String, mut body: Vec<u8>) -> HttpReply {
+    let length = body.len();
+    let etag = format!("\"{}\"", &to_hex(&Sha256::digest(&body))[..16]);
+    let start = request
+        .header("Range")
+        .and_then(|range| {
+            range
+                .strip_prefix("bytes=")?
+                .strip_suffix('-')?
+                .parse::<usize>()
+                .ok()
+        })
+        .filter(|_| {
+            request
+                .header("If-Range")
+                .is_none_or(|validator| validator == etag)
+        });
+
+    let mut headers = vec![("etag".into(), etag)];
+    let status = match start {
+        Some(start) if start >= length => {
+            headers.push(("content-range".into(), format!("bytes */{length}")));
+            body.clear();
+            416
+        }
+        Some(start) => {
+            headers.push((
+                "content-range".into(),
+                format!("bytes {start}-{}/{length}", length - 1),
+            ));
+            body.drain(..start);
+            206
+        }
+        None => 200,
+    };
+    headers.push(("content-length".into(), body.len().to_string()));
+
+    HttpReply {
+        status,
+        url,
+        headers,
+        reader: Box::new(Cursor::new(body)),
+    }
+}
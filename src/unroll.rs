@@ -0,0 +1,4453 @@
+/*!
+Extraction of archives
+
+Unrolling of __.tar.gz__ archives into directory, merging of several archives
+into one tree and rolling of directory back into archive.
+ */
+
+use std::{
+    cell::Cell,
+    collections::{
+        hash_map::{DefaultHasher, Entry as MapEntry},
+        HashMap, HashSet,
+    },
+    convert::TryFrom,
+    fmt::Write as FmtWrite,
+    fs::{
+        create_dir_all, hard_link, read_to_string, remove_dir_all, remove_file, rename, write, File,
+    },
+    hash::Hasher,
+    io::{
+        copy, sink, BufRead, BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind,
+        Read, Result as IoResult, Seek, SeekFrom, Write,
+    },
+    path::{Component, Path, PathBuf},
+    result::Result as StdResult,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Condvar, Mutex, OnceLock, PoisonError,
+    },
+    thread::{spawn, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::lchown;
+
+use libflate::gzip::{
+    Decoder as GzipDecoder, EncodeOptions, Encoder as GzipEncoder,
+    HeaderBuilder as GzipHeaderBuilder,
+};
+use tar::{
+    Archive as TarArchive, Builder as TarBuilder, Entry as TarEntry, EntryType as TarEntryType,
+    Header as TarHeader, HeaderMode,
+};
+
+use crate::{
+    cargo_warning, check_source, decode_error, known_failure, observe, open_source, out_dir,
+    parse_env, quarantine_file, rerun_if_changed, rerun_if_env_changed, send_chunks, set_file_mode,
+    sibling_path, sibling_temp_path, wildcard_match, CancelToken, Cancelled, CountReader,
+    CountWriter, EntryError, Error, ErrorChain, ErrorKind, Event, FileContext, Flag, LimitExceeded,
+    Metadata, OnStats, ProgressReader, Result, Status, Summary, TransferStats, ATOMIC_UNROLL,
+    BOUNDED_MEMORY, CHECK_FREE_SPACE, CLEANUP_DEST_DIR, CLEANUP_ON_ERROR, CONTINUE_ON_ERROR,
+    CREATE_DEST_PATH, DEDUP_FILES, DEFAULT_BUFFER_SIZE, DEFAULT_SPILL_THRESHOLD,
+    DEFAULT_UNROLL_FLAGS, DETECT_EXECUTABLES, DIFF_UPDATE, FIX_INVALID_DEST, FIX_LIBTOOL,
+    FIX_PKGCONFIG, FLATTEN_ENTRIES, GZIP_MAGIC, QUARANTINE, REPRODUCIBLE, RESUMABLE_UNROLL,
+    SKIP_TOO_DEEP, STRIP_TOPLEVEL, STRIP_WHEN_ALONE, THREADED_DECODE, UNPACK_XATTRS,
+};
+
+#[cfg(feature = "indicatif")]
+use crate::{progress_bar, report_progress};
+
+/// Archive unroller
+///
+/// *NOTE*: Currently supported __.tar.gz__ archives only.
+pub struct Unroll<R> {
+    pub(crate) source: Result<R>,
+    pub(crate) options: UnrollOptions,
+    pub(crate) metadata: Arc<OnceLock<Metadata>>,
+}
+
+/// Ownership policy
+///
+/// Determines what to do with user and group ids stored in archive headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ownership {
+    /// Ignore ids from archive
+    ///
+    /// Extracted entries will be owned by the user which runs extraction.
+    #[default]
+    Ignore,
+
+    /// Map ids to the current user
+    ///
+    /// Extracted entries will be explicitly owned by effective user and group of current process.
+    /// This also overrides group inherited from destination directory (setgid bit).
+    Current,
+
+    /// Apply ids from archive only when running privileged
+    ///
+    /// When current process has no privileges to change ownership it works like [`Ownership::Ignore`].
+    Privileged,
+}
+
+/// Extraction manifest
+///
+/// Describes entries which was actually extracted.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    /// Extracted entries in order of extraction
+    pub entries: Vec<ManifestEntry>,
+
+    /// Name of top-level directory which was stripped
+    pub toplevel: Option<String>,
+
+    /// Entries which was skipped in order of occurrence
+    pub skipped: Vec<SkippedEntry>,
+
+    /// Summary of operation
+    pub summary: Summary,
+}
+
+impl Manifest {
+    /// Iterate over paths of extracted files
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::File)
+            .map(|entry| entry.path.as_path())
+    }
+
+    /// Add extracted entry
+    fn push(&mut self, entry: ManifestEntry) {
+        observe(&Event::EntryExtracted { entry: &entry });
+        self.entries.push(entry);
+    }
+}
+
+/// Extracted entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Path to entry in destination directory
+    pub path: PathBuf,
+
+    /// Size of entry contents in bytes
+    pub size: u64,
+
+    /// Kind of entry
+    pub kind: EntryKind,
+}
+
+/// Skipped entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    /// Path of entry in archive
+    pub path: PathBuf,
+
+    /// Reason of skipping
+    pub reason: SkipReason,
+}
+
+/// Reason of skipping entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Filtered out by options (stripping, mapping, kinds and etc.)
+    Filtered,
+
+    /// Name is not valid UTF8
+    NonUtf8Name,
+
+    /// Name is not valid on Windows
+    WindowsName,
+
+    /// Path exceeds depth limit
+    TooDeep,
+
+    /// Entry already exists in destination and should not be overwritten
+    AlreadyExists,
+
+    /// Entry type is not supported
+    Unsupported,
+}
+
+/// Kind of extracted entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file
+    File,
+
+    /// Directory
+    Directory,
+
+    /// Symbolic link
+    Symlink,
+
+    /// Hard link to previously extracted file
+    HardLink,
+}
+
+impl EntryKind {
+    /// Get kind of supported archive entry type
+    const fn from_entry_type(type_: TarEntryType) -> Option<Self> {
+        Some(match type_ {
+            TarEntryType::Directory => Self::Directory,
+            TarEntryType::Symlink => Self::Symlink,
+            TarEntryType::Link => Self::HardLink,
+            type_ if is_file_entry(type_) => Self::File,
+            _ => return None,
+        })
+    }
+
+    /// Get bit of kind in kinds mask
+    const fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Case collision policy
+///
+/// Determines what to do with entries which names differs only by case
+/// (like `README` and `readme`) when destination filesystem is case-insensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseCollision {
+    /// Do not check names
+    ///
+    /// Colliding entries will silently overwrite each other.
+    #[default]
+    Ignore,
+
+    /// Fail extraction with error
+    Error,
+
+    /// Rename colliding entries by appending numeric suffix
+    ///
+    /// For example, `readme` will be extracted as `readme~1` when `README` already exists.
+    Rename,
+}
+
+/// Windows names policy
+///
+/// Determines what to do with entries which names are invalid on Windows,
+/// like names with characters `:`, `*`, `?`, trailing dots or reserved device names (`CON`, `NUL`).
+///
+/// *NOTE*: Takes effect on Windows only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowsNames {
+    /// Fail extraction with error
+    #[default]
+    Error,
+
+    /// Rename entries by replacing invalid characters with `_`
+    ///
+    /// Reserved names are suffixed by `_`, for example `nul.txt` becomes `nul_.txt`.
+    Rename,
+
+    /// Skip entries with invalid names
+    Skip,
+}
+
+/// Overwrite policy
+///
+/// Determines what to do with entries which already exist in destination directory.
+/// Directories are always merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+    /// Always overwrite existing entries
+    #[default]
+    Always,
+
+    /// Never overwrite existing entries (keep it as is)
+    Never,
+
+    /// Overwrite existing entries only when entry from archive is newer
+    IfNewer,
+
+    /// Overwrite existing files unless its size and modification time match the entry
+    ///
+    /// Makes repeated extraction into persistent directory much cheaper.
+    /// Entries other than files are always overwritten.
+    IfChanged,
+
+    /// Fail extraction with error
+    Error,
+}
+
+/// Entry path mapping function
+type MapPath = Arc<dyn Fn(&Path) -> Option<PathBuf>>;
+
+/// Entry progress function
+type OnEntry = Arc<dyn Fn(usize, Option<usize>, &Path)>;
+
+/// Warning reporter function
+type OnWarning = Arc<dyn Fn(&str)>;
+
+/// Contents mapper function
+type MapContents = Arc<dyn Fn(&Path, &mut dyn Read) -> Result<Option<Vec<u8>>>>;
+
+/// Post-extraction hook function
+type AfterUnroll = Arc<dyn Fn(&Path, &Manifest) -> Status>;
+
+/// Non-UTF8 names policy
+///
+/// Determines what to do with entries which names are not valid UTF8
+/// (common for archives created on Windows or legacy systems).
+/// The same policy is applied on all platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8Names {
+    /// Replace invalid sequences by `U+FFFD` replacement character
+    #[default]
+    Lossy,
+
+    /// Encode non-ASCII bytes as `%XX`
+    PercentEncode,
+
+    /// Skip entries with non-UTF8 names
+    Skip,
+
+    /// Fail extraction with error
+    Error,
+}
+
+/// Unsupported entries policy
+///
+/// Determines what to do with entries of unsupported types
+/// (like FIFOs or device files).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnsupportedEntries {
+    /// Skip entries silently
+    #[default]
+    Skip,
+
+    /// Skip entries and report warning via `on_warning` callback
+    Warn,
+
+    /// Fail extraction with error
+    Error,
+}
+
+/// Symlinks fallback policy
+///
+/// Determines what to do when symlinks cannot be created
+/// (common on Windows without developer mode or administrator privileges).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkFallback {
+    /// Fail extraction with error
+    #[default]
+    Error,
+
+    /// Copy link target (directories are copied recursively)
+    ///
+    /// Copies are made after extraction of other entries
+    /// and reported as symlinks in manifest.
+    Copy,
+}
+
+#[derive(Clone)]
+pub struct UnrollOptions {
+    pub strip_components: usize,
+    pub strip_prefix: Option<PathBuf>,
+    pub map_path: Option<MapPath>,
+    pub map_contents: Option<MapContents>,
+    pub on_entry: Option<OnEntry>,
+    pub on_stats: Option<OnStats>,
+    pub on_warning: Option<OnWarning>,
+    pub after_unroll: Option<AfterUnroll>,
+    pub overwrite: Overwrite,
+    pub ownership: Ownership,
+    pub case_collision: CaseCollision,
+    pub windows_names: WindowsNames,
+    pub non_utf8_names: NonUtf8Names,
+    pub unsupported_entries: UnsupportedEntries,
+    pub symlink_fallback: SymlinkFallback,
+    pub max_unpacked_size: Option<u64>,
+    pub max_entries: Option<usize>,
+    pub max_entry_size: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub dir_mode: Option<u32>,
+    pub max_compression_ratio: Option<u64>,
+    pub threads: usize,
+    pub skip_kinds: u8,
+    pub exec_patterns: Vec<String>,
+    pub archive_path: Option<PathBuf>,
+    pub cancel: Option<CancelToken>,
+    pub buffer_size: usize,
+    pub spill_threshold: u64,
+    #[cfg(feature = "mmap")]
+    pub mmap_threshold: Option<u64>,
+    /// Size of fetched data when known
+    pub size_hint: Option<u64>,
+    pub flags: Flag,
+}
+
+impl Default for UnrollOptions {
+    fn default() -> Self {
+        Self {
+            strip_components: 0,
+            strip_prefix: None,
+            map_path: None,
+            map_contents: None,
+            on_entry: None,
+            on_stats: None,
+            on_warning: None,
+            after_unroll: None,
+            overwrite: Overwrite::default(),
+            ownership: Ownership::default(),
+            case_collision: CaseCollision::default(),
+            windows_names: WindowsNames::default(),
+            non_utf8_names: NonUtf8Names::default(),
+            unsupported_entries: UnsupportedEntries::default(),
+            symlink_fallback: SymlinkFallback::default(),
+            max_unpacked_size: None,
+            max_entries: None,
+            max_entry_size: None,
+            max_depth: None,
+            dir_mode: None,
+            max_compression_ratio: None,
+            threads: 1,
+            skip_kinds: 0,
+            exec_patterns: Vec::new(),
+            archive_path: None,
+            cancel: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            spill_threshold: DEFAULT_SPILL_THRESHOLD,
+            #[cfg(feature = "mmap")]
+            mmap_threshold: None,
+            size_hint: None,
+            flags: DEFAULT_UNROLL_FLAGS,
+        }
+    }
+}
+
+impl<R> From<Result<R>> for Unroll<R> {
+    fn from(source: Result<R>) -> Self {
+        Self {
+            source,
+            options: UnrollOptions::default(),
+            metadata: Arc::default(),
+        }
+    }
+}
+
+impl Unroll<File> {
+    /// Unroll local archive file
+    ///
+    /// Works without `http` feature, like when archive is provided by other means.
+    #[must_use]
+    pub fn from_file<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        rerun_if_changed(path);
+        Self::from(
+            File::open(path)
+                .context("open file", path)
+                .map_err(Error::from),
+        )
+    }
+}
+
+/// Reusable configuration of unroller
+///
+/// It's constructed using the same methods as unroller
+/// and can be applied to many unrollers using [`Unroll::config`].
+pub type UnrollConfig = Unroll<()>;
+
+impl Default for UnrollConfig {
+    fn default() -> Self {
+        Self::from(Ok(()))
+    }
+}
+
+impl UnrollConfig {
+    /// Create default configuration
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<R> Unroll<R> {
+    /// Apply reusable configuration
+    ///
+    /// All options which was set before will be replaced.
+    #[must_use]
+    pub fn config(mut self, config: &UnrollConfig) -> Self {
+        self.options = config.options.clone();
+        self
+    }
+
+    /// Create destination directory when it doesn't exists
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn create_dest_path(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[CREATE_DEST_PATH] = flag }
+        self
+    }
+
+    /// Cleanup destination directory before extraction
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn cleanup_dest_dir(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[CLEANUP_DEST_DIR] = flag }
+        self
+    }
+
+    /// Try to fix destination path when it is not a valid
+    ///
+    /// For example, when destination already exists
+    /// and it is not a directory, it will be removed
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn fix_invalid_dest(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[FIX_INVALID_DEST] = flag }
+        self
+    }
+
+    /// Cleanup already extracted data when errors occurs
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn cleanup_on_error(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[CLEANUP_ON_ERROR] = flag }
+        self
+    }
+
+    /// Keep archive with mismatched hash saved by `save_archive` for inspection
+    ///
+    /// The path of kept file is reported in [`Error::Checksum`].
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn quarantine(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[QUARANTINE] = flag }
+        self
+    }
+
+    /// Strip the number of leading components from file names on extraction
+    ///
+    /// Default: `0`
+    #[must_use]
+    pub const fn strip_components(mut self, num_of_components: usize) -> Self {
+        self.options.strip_components = num_of_components;
+        self
+    }
+
+    /// Strip the exact leading path from file names on extraction
+    ///
+    /// Entries outside of this path will be skipped.
+    /// The `strip_components` is applied after stripping the prefix.
+    ///
+    /// Default: none
+    #[must_use]
+    pub fn strip_prefix<P>(mut self, prefix: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.options.strip_prefix = entry_path_stripped(prefix.as_ref(), 0);
+        self
+    }
+
+    /// Strip the leading components only when it's alone
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn strip_when_alone(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[STRIP_WHEN_ALONE] = flag }
+        self
+    }
+
+    /// Strip the top-level directory when it's alone
+    ///
+    /// The name of stripped directory is reported in [`Manifest::toplevel`].
+    /// Applied before `strip_prefix` and `strip_components`.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn strip_toplevel(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[STRIP_TOPLEVEL] = flag }
+        self
+    }
+
+    /// Restore extended file attributes of entries
+    ///
+    /// Attributes are taken from PAX extended headers (`SCHILY.xattr.*` records).
+    ///
+    /// *NOTE*: Currently supported on Unix only.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn unpack_xattrs(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[UNPACK_XATTRS] = flag }
+        self
+    }
+
+    /// Case collision policy for case-insensitive filesystems
+    ///
+    /// Default: [`CaseCollision::Ignore`]
+    #[must_use]
+    pub const fn case_collision(mut self, policy: CaseCollision) -> Self {
+        self.options.case_collision = policy;
+        self
+    }
+
+    /// Windows names policy for entries with invalid names
+    ///
+    /// Default: [`WindowsNames::Error`]
+    #[must_use]
+    pub const fn windows_names(mut self, policy: WindowsNames) -> Self {
+        self.options.windows_names = policy;
+        self
+    }
+
+    /// Non-UTF8 names policy for entries
+    ///
+    /// Default: [`NonUtf8Names::Lossy`]
+    #[must_use]
+    pub const fn non_utf8_names(mut self, policy: NonUtf8Names) -> Self {
+        self.options.non_utf8_names = policy;
+        self
+    }
+
+    /// Symlinks fallback policy when symlinks cannot be created
+    ///
+    /// Default: [`SymlinkFallback::Error`]
+    #[must_use]
+    pub const fn symlink_fallback(mut self, policy: SymlinkFallback) -> Self {
+        self.options.symlink_fallback = policy;
+        self
+    }
+
+    /// Unsupported entries policy
+    ///
+    /// Default: [`UnsupportedEntries::Skip`]
+    #[must_use]
+    pub const fn unsupported_entries(mut self, policy: UnsupportedEntries) -> Self {
+        self.options.unsupported_entries = policy;
+        self
+    }
+
+    /// Limit total size of extracted data in bytes
+    ///
+    /// Extraction will be aborted when the sum of entry sizes exceeds the limit.
+    ///
+    /// Default: unlimited
+    #[must_use]
+    pub const fn max_unpacked_size(mut self, bytes: u64) -> Self {
+        self.options.max_unpacked_size = Some(bytes);
+        self
+    }
+
+    /// Limit the number of entries in archive
+    ///
+    /// Default: unlimited
+    #[must_use]
+    pub const fn max_entries(mut self, num_of_entries: usize) -> Self {
+        self.options.max_entries = Some(num_of_entries);
+        self
+    }
+
+    /// Limit size of single entry in bytes
+    ///
+    /// Default: unlimited
+    #[must_use]
+    pub const fn max_entry_size(mut self, bytes: u64) -> Self {
+        self.options.max_entry_size = Some(bytes);
+        self
+    }
+
+    /// Limit the number of components in paths of extracted entries
+    ///
+    /// The depth is checked after stripping and mapping paths.
+    ///
+    /// Default: unlimited
+    #[must_use]
+    pub const fn max_depth(mut self, depth: usize) -> Self {
+        self.options.max_depth = Some(depth);
+        self
+    }
+
+    /// Skip entries which exceeds `max_depth` instead of failing
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn skip_too_deep(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[SKIP_TOO_DEEP] = flag }
+        self
+    }
+
+    /// Limit the ratio of decompressed size to compressed size
+    ///
+    /// The ratio is checked only after first megabyte of data has been decompressed,
+    /// because tiny archives usually have high ratios due to tar blocks padding.
+    ///
+    /// Default: unlimited
+    #[must_use]
+    pub const fn max_compression_ratio(mut self, ratio: u64) -> Self {
+        self.options.max_compression_ratio = Some(ratio);
+        self
+    }
+
+    /// Number of threads for writing extracted files
+    ///
+    /// Entries are decoded sequentially while contents of files are written
+    /// by the pool of worker threads which speeds up extraction of many small files.
+    /// Ignored in `resumable` mode and when `unpack_xattrs` is set.
+    ///
+    /// Default: `1`
+    #[must_use]
+    pub const fn threads(mut self, num_of_threads: usize) -> Self {
+        self.options.threads = num_of_threads;
+        self
+    }
+
+    /// Map entry paths during extraction
+    ///
+    /// The function receives entry path relative to destination (after stripping components)
+    /// and returns new relative path or `None` to skip entry.
+    /// For example, it can be used to move `lib64/` to `lib/`.
+    #[must_use]
+    pub fn map_path<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Path) -> Option<PathBuf> + 'static,
+    {
+        self.options.map_path = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Map contents of files during extraction
+    ///
+    /// The function receives file path relative to destination and the reader of its contents
+    /// and returns new contents or `None` to skip file.
+    /// For example, it can be used to fix shebangs or rewrite absolute paths in text files.
+    ///
+    /// *NOTE*: Mapped files are kept in memory entirely.
+    #[must_use]
+    pub fn map_contents<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Path, &mut dyn Read) -> Result<Option<Vec<u8>>> + 'static,
+    {
+        self.options.map_contents = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Extract into temporary directory and move it to destination on success
+    ///
+    /// The staging directory is created near to destination and replaces it entirely
+    /// only when extraction succeeded, so destination is never left half-populated.
+    /// The `cleanup_dest_dir` and `cleanup_on_error` options does not affect this mode.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn atomic(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[ATOMIC_UNROLL] = flag }
+        self
+    }
+
+    /// Keep partially extracted data on errors and resume extraction next time
+    ///
+    /// On error the extracted entries are kept along with progress record
+    /// (hidden file near to destination). The next extraction skips already extracted entries
+    /// instead of starting from scratch. The archive is still downloaded entirely.
+    /// The `cleanup_on_error` option does not affect this mode.
+    /// Cannot be combined with `atomic` mode.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn resumable(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[RESUMABLE_UNROLL] = flag }
+        self
+    }
+
+    /// Report progress of extraction
+    ///
+    /// The function is called before extraction of each entry with the index of entry in archive,
+    /// the total number of entries (when it known) and the entry path relative to destination.
+    /// When error occurs the last reported entry is the failed one.
+    #[must_use]
+    pub fn on_entry<F>(mut self, reporter: F) -> Self
+    where
+        F: Fn(usize, Option<usize>, &Path) + 'static,
+    {
+        self.options.on_entry = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Report statistics of downloading
+    ///
+    /// The function is called after each received chunk of archive
+    /// with speed and estimated remaining time.
+    #[must_use]
+    pub fn on_stats<F>(mut self, reporter: F) -> Self
+    where
+        F: Fn(&TransferStats) + 'static,
+    {
+        self.options.on_stats = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Show progress of downloading on terminal
+    ///
+    /// The bar is drawn to stderr when it's a terminal.
+    /// Replaces function set by `on_stats`.
+    #[cfg(feature = "indicatif")]
+    #[must_use]
+    pub fn show_progress(mut self) -> Self {
+        self.options.on_stats = Some(report_progress(progress_bar(), true));
+        self
+    }
+
+    /// Report progress of downloading to specified bar
+    ///
+    /// The length and position of bar are updated, its style is kept as is.
+    /// Replaces function set by `on_stats`.
+    #[cfg(feature = "indicatif")]
+    #[must_use]
+    pub fn progress_bar(mut self, bar: indicatif::ProgressBar) -> Self {
+        self.options.on_stats = Some(report_progress(bar, false));
+        self
+    }
+
+    /// Report non-fatal issues of extraction
+    ///
+    /// The function is called with the warning message.
+    #[must_use]
+    pub fn on_warning<F>(mut self, reporter: F) -> Self
+    where
+        F: Fn(&str) + 'static,
+    {
+        self.options.on_warning = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Process extracted contents before completion
+    ///
+    /// The function is called with the destination path and the manifest of extracted entries.
+    /// Its errors are handled like extraction errors (according to `cleanup_on_error`).
+    /// In `atomic` mode it is called on staging directory before moving to destination.
+    #[must_use]
+    pub fn after_unroll<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Path, &Manifest) -> Status + 'static,
+    {
+        self.options.after_unroll = Some(Arc::new(hook));
+        self
+    }
+
+    /// Drop directory structure and extract all files directly into destination
+    ///
+    /// Directories and symlinks will be skipped.
+    /// Files with the same names are handled according to `overwrite` policy.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn flatten(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[FLATTEN_ENTRIES] = flag }
+        self
+    }
+
+    /// Permissions mode of directories created during extraction
+    ///
+    /// Applied to directory entries and intermediate directories
+    /// (including created destination) regardless of umask.
+    ///
+    /// *NOTE*: Currently supported on Unix only.
+    ///
+    /// Default: mode from archive (for directory entries) or umask-based
+    #[must_use]
+    pub const fn dir_mode(mut self, mode: u32) -> Self {
+        self.options.dir_mode = Some(mode);
+        self
+    }
+
+    /// Skip entries of the specified kind
+    ///
+    /// Can be called multiple times to skip several kinds of entries.
+    /// Parent directories of extracted entries are created anyway.
+    ///
+    /// Default: none
+    #[must_use]
+    pub const fn skip_kind(mut self, kind: EntryKind) -> Self {
+        self.options.skip_kinds |= kind.mask();
+        self
+    }
+
+    /// Extract only files skipping directories and symlinks
+    ///
+    /// Hard links are extracted as files.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn only_files(mut self, flag: bool) -> Self {
+        let mask = EntryKind::Directory.mask() | EntryKind::Symlink.mask();
+        if flag {
+            self.options.skip_kinds |= mask;
+        } else {
+            self.options.skip_kinds &= !mask;
+        }
+        self
+    }
+
+    /// Continue extraction when some entries failed
+    ///
+    /// Errors of individual entries are collected and reported at the end
+    /// with [`Error::Entries`]. The successfully extracted entries are kept
+    /// regardless of `cleanup_on_error` (except of `atomic` mode).
+    /// Limits violations and archive corruptions abort extraction anyway.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn continue_on_error(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[CONTINUE_ON_ERROR] = flag }
+        self
+    }
+
+    /// Rewrite `prefix=` lines of extracted pkg-config (`.pc`) files to the destination path
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn fix_pkgconfig(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[FIX_PKGCONFIG] = flag }
+        self
+    }
+
+    /// Rewrite `libdir=` lines of extracted libtool (`.la`) files to the actual directory
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn fix_libtool(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[FIX_LIBTOOL] = flag }
+        self
+    }
+
+    /// Make files matching the pattern executable
+    ///
+    /// The pattern may contain `*` (any characters except `/`) and `?` (any single character).
+    /// Patterns without `/` matches file names (like `*.sh`),
+    /// other patterns matches paths relative to destination (like `bin/*`).
+    /// Can be called multiple times to add several patterns.
+    ///
+    /// *NOTE*: Currently supported on Unix only.
+    ///
+    /// Default: none
+    #[must_use]
+    pub fn exec_pattern<S>(mut self, pattern: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.options.exec_patterns.push(pattern.into());
+        self
+    }
+
+    /// Make files executable when its contents is ELF or Mach-O binary
+    ///
+    /// *NOTE*: Currently supported on Unix only.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn detect_executables(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[DETECT_EXECUTABLES] = flag }
+        self
+    }
+
+    /// Extract files with identical contents as hard links to the first one
+    ///
+    /// Files are considered identical when contents and permissions are the same.
+    /// Such files are still reported as regular files in manifest.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn dedup_files(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[DEDUP_FILES] = flag }
+        self
+    }
+
+    /// Update previously extracted contents of destination differentially
+    ///
+    /// Files which contents is not changed are kept untouched (including modification time)
+    /// and entries which was extracted before but no longer present in archive are removed.
+    /// The list of extracted entries is recorded in hidden file near to destination.
+    /// The `cleanup_dest_dir` option does not affect this mode.
+    /// Cannot be combined with `atomic` mode.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn update(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[DIFF_UPDATE] = flag }
+        self
+    }
+
+    /// Overwrite policy for entries which already exist
+    ///
+    /// Makes sense when `cleanup_dest_dir` is not set or when `flatten` is set.
+    ///
+    /// Default: [`Overwrite::Always`]
+    #[must_use]
+    pub const fn overwrite(mut self, policy: Overwrite) -> Self {
+        self.options.overwrite = policy;
+        self
+    }
+
+    /// Ownership policy for extracted entries
+    ///
+    /// Default: [`Ownership::Ignore`]
+    #[must_use]
+    pub const fn ownership(mut self, policy: Ownership) -> Self {
+        self.options.ownership = policy;
+        self
+    }
+
+    /// Save source archive to specified path while extracting
+    ///
+    /// The archive is written in the same pass as extraction
+    /// so it will not be downloaded or read twice.
+    /// It's moved to specified path only when the whole archive was received.
+    ///
+    /// Default: none
+    ///
+    /// The archive with mismatched hash is kept as `<name>.corrupt` when `quarantine` is set.
+    #[must_use]
+    pub fn save_archive<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.options.archive_path = Some(path.as_ref().into());
+        self
+    }
+
+    /// Size of buffer used for reading of archive
+    ///
+    /// Larger buffers improve throughput on high-bandwidth links.
+    ///
+    /// Default: `262144` (256 KiB)
+    #[must_use]
+    pub const fn buffer_size(mut self, size: usize) -> Self {
+        self.options.buffer_size = size;
+        self
+    }
+
+    /// Maximum size of decoded archive which is buffered in memory
+    ///
+    /// Archive should be buffered when `strip_toplevel` or `strip_when_alone` is used,
+    /// larger archives are spilled to temporary file instead of memory.
+    ///
+    /// Default: `67108864` (64 MiB)
+    #[must_use]
+    pub const fn spill_threshold(mut self, size: u64) -> Self {
+        self.options.spill_threshold = size;
+        self
+    }
+
+    /// Produce identical trees on any machine
+    ///
+    /// Modification times of files and directories are set to `SOURCE_DATE_EPOCH`
+    /// (Unix epoch when it's not set), files get mode `0o644` or `0o755` when executable,
+    /// directories get `dir_mode` or `0o755`. Files are written sequentially in order of archive
+    /// (`threads` is ignored).
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn reproducible(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[REPRODUCIBLE] = flag }
+        self
+    }
+
+    /// Check free space of destination before extraction
+    ///
+    /// The required space is estimated using sizes of entries when archive is buffered
+    /// (like when `strip_toplevel` is used) or using `Content-Length` otherwise.
+    /// Note that `Content-Length` is the size of compressed data so it's only the lower bound.
+    /// The check is done before destination is touched, so the space occupied by its
+    /// existing contents is not counted as free.
+    /// The check is skipped when free space cannot be determined (like on non-Unix systems).
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn check_free_space(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[CHECK_FREE_SPACE] = flag }
+        self
+    }
+
+    /// Extract using memory which doesn't depend on size of archive
+    ///
+    /// Data is streamed through buffers of `buffer_size`, archive which should be buffered
+    /// is always spilled to temporary file and contents of entries is never kept in memory.
+    /// Only metadata of entries (like paths) is accumulated.
+    ///
+    /// Cannot be combined with options which require whole contents of entries:
+    /// `threads`, `dedup_files`, `update`, `detect_executables`, `exec_pattern` and `map_contents`.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn bounded_memory(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[BOUNDED_MEMORY] = flag }
+        self
+    }
+
+    /// Decompress archive in separate thread
+    ///
+    /// Decompression runs in parallel with downloading and writing of files,
+    /// so it doesn't wait for them on big archives.
+    ///
+    /// Only gzip archives are supported currently, and gzip stream cannot be
+    /// split between workers, so the decoding runs in one extra thread
+    /// and doesn't use more than one core itself.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn threaded_decode(mut self, flag: bool) -> Self {
+        flag! { self.options.flags[THREADED_DECODE] = flag }
+        self
+    }
+
+    /// Write files of specified size or larger through memory mapping
+    ///
+    /// Files are pre-allocated before writing which reduces fragmentation and syscalls.
+    /// It's used only when contents isn't processed before writing.
+    ///
+    /// Default: `None`
+    #[cfg(feature = "mmap")]
+    #[must_use]
+    pub const fn mmap_threshold(mut self, size: Option<u64>) -> Self {
+        self.options.mmap_threshold = size;
+        self
+    }
+
+    /// Abort extraction when cancellation is requested using token
+    ///
+    /// Default: not set
+    #[must_use]
+    pub fn cancel_token(mut self, token: &CancelToken) -> Self {
+        self.options.cancel = Some(token.clone());
+        self
+    }
+}
+
+impl<R> Unroll<R> {
+    /// Check url and options before any network or file system operations
+    ///
+    /// Called implicitly by terminal methods like [`Unroll::to`].
+    ///
+    /// # Errors
+    /// - Url is invalid or its scheme is not supported
+    /// - Options conflict with each other
+    pub fn validate(&self) -> Status {
+        check_source(&self.source)?;
+        if flag!(self.options.flags[ATOMIC_UNROLL]) {
+            for (flag, name) in [(RESUMABLE_UNROLL, "resumable"), (DIFF_UPDATE, "update")] {
+                if self.options.flags & flag != 0 {
+                    return Err(Error::Config(format!(
+                        "`atomic` cannot be combined with `{name}`"
+                    )));
+                }
+            }
+        }
+        if flag!(self.options.flags[BOUNDED_MEMORY]) {
+            let options = &self.options;
+            for (conflicts, name) in [
+                (options.threads > 1, "threads"),
+                (flag!(options.flags[DEDUP_FILES]), "dedup_files"),
+                (flag!(options.flags[DIFF_UPDATE]), "update"),
+                (
+                    flag!(options.flags[DETECT_EXECUTABLES]),
+                    "detect_executables",
+                ),
+                (!options.exec_patterns.is_empty(), "exec_pattern"),
+                (options.map_contents.is_some(), "map_contents"),
+            ] {
+                if conflicts {
+                    return Err(Error::Config(format!(
+                        "`bounded_memory` cannot be combined with `{name}`"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract contents to specified directory
+    ///
+    /// Returns the manifest of extracted entries.
+    ///
+    /// # Errors
+    /// - Destination directory does not exists when `create_dest_path` is not set
+    /// - Destination directory is not empty when `cleanup_dest_dir` is not set
+    /// - Destination path is not a directory when `fix_invalid_dest` is not set
+    /// - Required number of path components cannot be stripped  when `strip_when_alone` is not set
+    pub fn to<D>(self, path: D) -> Result<Manifest>
+    where
+        R: Read,
+        D: AsRef<Path>,
+    {
+        self.validate()?;
+        let started = Instant::now();
+        let Self {
+            source,
+            mut options,
+            metadata,
+        } = self;
+
+        let downloaded = Cell::new(0);
+        let mut source = open_source(source, options.cancel.as_ref(), options.buffer_size, true)?;
+        check_archive(&mut source, metadata.get())?;
+        // Metadata is available once response is received
+        options.size_hint = metadata.get().and_then(|metadata| metadata.content_length);
+        let source = ProgressReader::with_reporters(
+            CountReader::new(source, &downloaded),
+            0,
+            options.size_hint,
+            None,
+            options.on_stats.as_ref(),
+        );
+
+        let path = path.as_ref();
+
+        let mut manifest = if let Some(archive_path) = &options.archive_path {
+            let mut source = TeeReader::create(source, archive_path, &options)?;
+            let mut result = unroll_to(&mut source, &options, path);
+
+            match &mut result {
+                // Archive itself is fine when some entries failed
+                Ok(_) | Err(Error::Entries(_)) => source.finish()?,
+                Err(error) if flag!(options.flags[QUARANTINE]) => source.quarantine(error)?,
+                Err(_) => source.abort()?,
+            }
+
+            result?
+        } else {
+            unroll_to(source, &options, path)?
+        };
+
+        manifest.summary = Summary {
+            bytes_downloaded: downloaded.get(),
+            bytes_written: manifest
+                .entries
+                .iter()
+                .filter(|entry| entry.kind == EntryKind::File)
+                .map(|entry| entry.size)
+                .sum(),
+            entries: manifest.entries.len(),
+            elapsed: started.elapsed(),
+            url: metadata.get().and_then(|metadata| metadata.url.clone()),
+            cached: manifest.entries.is_empty()
+                && manifest
+                    .skipped
+                    .iter()
+                    .any(|entry| entry.reason == SkipReason::AlreadyExists),
+        };
+
+        observe(&Event::Finished {
+            summary: &manifest.summary,
+        });
+        Ok(manifest)
+    }
+
+    /// Extract contents to specified directory inside of `OUT_DIR`
+    ///
+    /// # Errors
+    /// - Variable `OUT_DIR` is not set (not running in build script)
+    /// - Same as for [`Unroll::to`]
+    pub fn to_out_dir<D>(self, path: D) -> Result<Manifest>
+    where
+        R: Read,
+        D: AsRef<Path>,
+    {
+        self.to(out_dir()?.join(path))
+    }
+}
+
+/// Check that data starts like an archive to report error pages clearly
+fn check_archive<R>(source: &mut R, metadata: Option<&Metadata>) -> Status
+where
+    R: BufRead,
+{
+    /// Number of bytes included into error
+    const HEAD_SIZE: usize = 64;
+
+    let data = source.fill_buf()?;
+    if !data.is_empty() && GZIP_MAGIC.starts_with(&data[..data.len().min(GZIP_MAGIC.len())]) {
+        return Ok(());
+    }
+    Err(Error::NotArchive {
+        url: metadata.and_then(|metadata| metadata.url.clone()),
+        content_type: metadata.and_then(|metadata| metadata.content_type.clone()),
+        head: String::from_utf8_lossy(&data[..data.len().min(HEAD_SIZE)])
+            .chars()
+            .map(|char| if char.is_control() { ' ' } else { char })
+            .collect::<String>()
+            .trim()
+            .into(),
+    })
+}
+
+/// Extract archive to destination directory
+fn unroll_to<R>(source: R, options: &UnrollOptions, path: &Path) -> Result<Manifest>
+where
+    R: Read,
+{
+    if flag!(options.flags[ATOMIC_UNROLL]) {
+        return unroll_archive_atomic(source, options, path);
+    }
+
+    let progress_path = if flag!(options.flags[RESUMABLE_UNROLL]) {
+        Some(sibling_path(path, "progress")?)
+    } else {
+        None
+    };
+
+    let mut progress = match &progress_path {
+        Some(progress_path) if path.is_dir() => Progress::load(progress_path)?,
+        _ => Progress::default(),
+    };
+
+    let record_path = if flag!(options.flags[DIFF_UPDATE]) {
+        Some(sibling_path(path, "manifest")?)
+    } else {
+        None
+    };
+
+    let dest_already_exists = path.is_dir();
+    let keep_entries = progress.resumed > 0 || record_path.is_some();
+    // Destination is touched only when archive looks valid
+    let prepared = Cell::new(false);
+    let prepare = || {
+        prepared.set(true);
+        if path.is_dir() {
+            // Partially extracted entries should be kept when resuming or updating
+            if flag!(options.flags[CLEANUP_DEST_DIR]) && !keep_entries {
+                remove_dir_entries(path)?;
+            }
+        } else if path.is_file() {
+            if flag!(options.flags[FIX_INVALID_DEST]) {
+                remove_file(path).context("remove file", path)?;
+
+                if flag!(options.flags[CREATE_DEST_PATH]) {
+                    create_dirs(path, options.dir_mode)?;
+                }
+            }
+        } else {
+            // not exists
+            if flag!(options.flags[CREATE_DEST_PATH]) {
+                create_dirs(path, options.dir_mode)?;
+            }
+        }
+        Ok(())
+    };
+
+    let mut result = unroll_archive_to(source, options, path, path, &mut progress, prepare);
+
+    if let Some(record_path) = &record_path {
+        result = result.and_then(|manifest| {
+            update_entries_record(record_path, path, &manifest)?;
+            Ok(manifest)
+        });
+    }
+
+    if let Some(progress_path) = &progress_path {
+        return match result {
+            // Failed entries should be retried next time from scratch
+            Ok(_) | Err(Error::Entries(_)) => {
+                if progress_path.is_file() {
+                    remove_file(progress_path).context("remove file", progress_path)?;
+                }
+                result
+            }
+            Err(error) => {
+                progress.save(progress_path)?;
+                Err(error)
+            }
+        };
+    }
+
+    // Previously extracted tree should be kept when update fails
+    if record_path.is_some() {
+        return result;
+    }
+
+    result.or_else(|error| {
+        if flag!(options.flags[CLEANUP_ON_ERROR])
+            && prepared.get()
+            && path.is_dir()
+            && !matches!(error, Error::Entries(_))
+        {
+            log_record!(debug, "Cleaning up `{}`", path.display());
+            observe(&Event::Cleanup { path });
+            if dest_already_exists {
+                remove_dir_entries(path)?;
+            } else {
+                remove_dir_all(path).context("remove directory", path)?;
+            }
+        }
+        Err(error)
+    })
+}
+
+impl<R> Unroll<R> {
+    /// Extract contents into memory
+    ///
+    /// Returns the contents of file entries by its paths.
+    /// Hard links are resolved to copies of target contents, other entries are skipped.
+    ///
+    /// Useful for small archives which contents should be consumed by build script itself.
+    ///
+    /// # Errors
+    /// - Fetching or decoding of archive failed
+    /// - Extraction limits exceeded
+    /// - Option `bounded_memory` is set
+    pub fn to_memory(self) -> Result<HashMap<PathBuf, Vec<u8>>>
+    where
+        R: Read,
+    {
+        self.validate()?;
+        if flag!(self.options.flags[BOUNDED_MEMORY]) {
+            return Err(Error::Config(
+                "`bounded_memory` cannot be combined with extraction into memory".into(),
+            ));
+        }
+        let Self {
+            source,
+            options,
+            metadata,
+        } = self;
+
+        let mut source = open_source(source, options.cancel.as_ref(), options.buffer_size, true)?;
+        check_archive(&mut source, metadata.get())?;
+
+        unroll_archive(source, &options, |archive, layout| {
+            unroll_entries_to_memory(archive, &layout, &options)
+        })
+    }
+
+    /// Extract executable of prebuilt tool into `OUT_DIR`
+    ///
+    /// The file named `name` (with `.exe` suffix on Windows) is searched in archive,
+    /// extracted into `$OUT_DIR/tools/<name>/` and made executable.
+    /// Useful when build script depends on external code generator like `protoc`.
+    ///
+    /// Returns the path to extracted executable.
+    ///
+    /// *NOTE*: The path mapper set by [`Unroll::map_path`] is replaced.
+    ///
+    /// # Errors
+    /// - Variable `OUT_DIR` is not set (not running in build script)
+    /// - Archive doesn't contain the executable
+    /// - Fetching or extraction failed
+    pub fn to_tool<N>(self, name: N) -> Result<PathBuf>
+    where
+        R: Read,
+        N: AsRef<str>,
+    {
+        self.tool_to(name.as_ref(), &out_dir()?)
+    }
+
+    pub(crate) fn tool_to(self, name: &str, out_dir: &Path) -> Result<PathBuf>
+    where
+        R: Read,
+    {
+        let file_name = if cfg!(windows) {
+            format!("{name}.exe")
+        } else {
+            name.into()
+        };
+        let dest = out_dir.join("tools").join(name);
+        // Destination is replaced only when executable is extracted
+        let staging_path = sibling_temp_path(&dest, "staging")?;
+
+        let result = self
+            .only_files(true)
+            .map_path({
+                let file_name = file_name.clone();
+                move |path| {
+                    (path.file_name() == Some(file_name.as_ref())).then(|| file_name.clone().into())
+                }
+            })
+            .exec_pattern(file_name.clone())
+            .to(&staging_path)
+            .and_then(|manifest| {
+                let path = staging_path.join(&file_name);
+                if !manifest.entries.iter().any(|entry| entry.path == path) {
+                    return Err(IoError::new(
+                        IoErrorKind::NotFound,
+                        format!("Executable `{name}` not found in archive"),
+                    )
+                    .into());
+                }
+                replace_path(&staging_path, &dest)
+            });
+
+        if let Err(error) = result {
+            if staging_path.exists() {
+                remove_dir_all(&staging_path).context("remove directory", &staging_path)?;
+            }
+            return Err(error);
+        }
+
+        Ok(dest.join(file_name))
+    }
+}
+
+/// Deferred extraction of archive into directory
+type MergeUnroll<'a> = Box<dyn FnOnce(&Path) -> Result<Manifest> + 'a>;
+
+/// Multiple archives unroller
+///
+/// Extracts several archives into the same destination,
+/// each optionally under its own prefix subdirectory.
+/// Destination is cleaned up once before extraction of the first archive
+/// so archives does not wipe contents of each other.
+pub struct Merge<'a> {
+    unrolls: Vec<(Option<PathBuf>, MergeUnroll<'a>)>,
+    flags: Flag,
+}
+
+impl Default for Merge<'_> {
+    fn default() -> Self {
+        Self {
+            unrolls: Vec::new(),
+            flags: DEFAULT_UNROLL_FLAGS,
+        }
+    }
+}
+
+impl<'a> Merge<'a> {
+    /// Create empty merge
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add archive to extract into destination directory
+    ///
+    /// The `atomic`, `cleanup_dest_dir` and `cleanup_on_error` options of unroll are ignored.
+    #[must_use]
+    pub fn unroll<R>(mut self, unroll: Unroll<R>) -> Self
+    where
+        R: Read + 'a,
+    {
+        self.unrolls.push((None, Self::prepare(unroll)));
+        self
+    }
+
+    /// Add archive to extract into prefix subdirectory of destination
+    ///
+    /// The `atomic`, `cleanup_dest_dir` and `cleanup_on_error` options of unroll are ignored.
+    #[must_use]
+    pub fn unroll_into<P, R>(mut self, prefix: P, unroll: Unroll<R>) -> Self
+    where
+        P: AsRef<Path>,
+        R: Read + 'a,
+    {
+        let prefix = entry_path_stripped(prefix.as_ref(), 0);
+        self.unrolls.push((prefix, Self::prepare(unroll)));
+        self
+    }
+
+    fn prepare<R>(unroll: Unroll<R>) -> MergeUnroll<'a>
+    where
+        R: Read + 'a,
+    {
+        let unroll = unroll
+            .create_dest_path(true)
+            .cleanup_dest_dir(false)
+            .cleanup_on_error(false)
+            .atomic(false);
+        Box::new(move |path| unroll.to(path))
+    }
+
+    /// Create destination directory when it doesn't exists
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn create_dest_path(mut self, flag: bool) -> Self {
+        flag! { self.flags[CREATE_DEST_PATH] = flag }
+        self
+    }
+
+    /// Cleanup destination directory before extraction
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn cleanup_dest_dir(mut self, flag: bool) -> Self {
+        flag! { self.flags[CLEANUP_DEST_DIR] = flag }
+        self
+    }
+
+    /// Cleanup already extracted data when errors occurs
+    ///
+    /// Default: `true`
+    #[must_use]
+    pub const fn cleanup_on_error(mut self, flag: bool) -> Self {
+        flag! { self.flags[CLEANUP_ON_ERROR] = flag }
+        self
+    }
+
+    /// Extract all archives to specified directory
+    ///
+    /// Returns the combined manifest of extracted entries.
+    /// Its summary has url only when all archives are fetched from the same url
+    /// and it's cached only when all archives are cached.
+    ///
+    /// # Errors
+    /// - Destination directory does not exists when `create_dest_path` is not set
+    /// - Extraction of some archive failed
+    pub fn to<D>(self, path: D) -> Result<Manifest>
+    where
+        D: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let dest_already_exists = path.is_dir();
+
+        if dest_already_exists {
+            if flag!(self.flags[CLEANUP_DEST_DIR]) {
+                remove_dir_entries(path)?;
+            }
+        } else if flag!(self.flags[CREATE_DEST_PATH]) {
+            create_dir_all(path).context("create directory", path)?;
+        } else {
+            return Err(IoError::new(
+                IoErrorKind::NotFound,
+                format!("Destination `{}` does not exists", path.display()),
+            )
+            .into());
+        }
+
+        let started = Instant::now();
+        let mut manifest = Manifest::default();
+        let mut urls = Vec::new();
+        let mut cached = true;
+
+        for (prefix, unroll) in self.unrolls {
+            let dest_path = prefix.map_or_else(|| path.into(), |prefix| path.join(prefix));
+
+            match unroll(&dest_path) {
+                Ok(unrolled) => {
+                    manifest.entries.extend(unrolled.entries);
+                    manifest.skipped.extend(unrolled.skipped);
+                    manifest.summary.bytes_downloaded += unrolled.summary.bytes_downloaded;
+                    manifest.summary.bytes_written += unrolled.summary.bytes_written;
+                    urls.push(unrolled.summary.url);
+                    cached &= unrolled.summary.cached;
+                }
+                Err(error) => {
+                    if flag!(self.flags[CLEANUP_ON_ERROR]) && path.is_dir() {
+                        observe(&Event::Cleanup { path });
+                        let cleanup = if dest_already_exists {
+                            remove_dir_entries(path)
+                        } else {
+                            remove_dir_all(path).context("remove directory", path)
+                        };
+                        // Error of extraction is more important
+                        if let Err(cleanup_error) = cleanup {
+                            log_record!(
+                                warn,
+                                "Unable to cleanup `{}`: {}",
+                                path.display(),
+                                ErrorChain(&cleanup_error)
+                            );
+                        }
+                    }
+                    return Err(error);
+                }
+            }
+        }
+
+        manifest.summary.entries = manifest.entries.len();
+        manifest.summary.elapsed = started.elapsed();
+        manifest.summary.cached = cached && !urls.is_empty();
+        manifest.summary.url = urls
+            .first()
+            .cloned()
+            .flatten()
+            .filter(|url| urls.iter().all(|other| other.as_ref() == Some(url)));
+
+        observe(&Event::Finished {
+            summary: &manifest.summary,
+        });
+        Ok(manifest)
+    }
+}
+
+/// Function which selects paths to roll
+type RollFilter = Box<dyn Fn(&Path) -> bool>;
+
+/// Directory roller
+///
+/// Packs directory into `.tar.gz` archive which can be unrolled later (the inverse of [`Unroll`]).
+/// Entries are added in order of names and symlinks are stored as is.
+pub struct Roll {
+    source: PathBuf,
+    prefix: Option<PathBuf>,
+    exclude_patterns: Vec<String>,
+    filter: Option<RollFilter>,
+    flags: Flag,
+}
+
+impl Roll {
+    /// Create roller of directory
+    pub fn new<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            source: path.as_ref().into(),
+            prefix: None,
+            exclude_patterns: Vec::new(),
+            filter: None,
+            flags: 0,
+        }
+    }
+
+    /// Put entries into prefix directory of archive
+    ///
+    /// Default: none
+    #[must_use]
+    pub fn prefix<P>(mut self, prefix: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.prefix =
+            entry_path_stripped(prefix.as_ref(), 0).filter(|path| !path.as_os_str().is_empty());
+        self
+    }
+
+    /// Skip entries which matches wildcard pattern
+    ///
+    /// Patterns are matched like in [`Unroll::exec_pattern`] using paths relative to source.
+    /// Contents of skipped directories is skipped too.
+    /// Can be called multiple times to add several patterns.
+    ///
+    /// Default: none
+    #[must_use]
+    pub fn exclude<S>(mut self, pattern: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.exclude_patterns.push(pattern.into());
+        self
+    }
+
+    /// Select entries using function
+    ///
+    /// The function receives path relative to source and returns `false` to skip entry.
+    ///
+    /// Default: none
+    #[must_use]
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Path) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Produce identical archives on any machine
+    ///
+    /// Modification times are set to `SOURCE_DATE_EPOCH` (Unix epoch when it's not set),
+    /// owners are reset to root, files get mode `0o644` or `0o755` when executable
+    /// and directories get `0o755`. The timestamp of gzip stream is omitted.
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub const fn reproducible(mut self, flag: bool) -> Self {
+        flag! { self.flags[REPRODUCIBLE] = flag }
+        self
+    }
+
+    /// Write archive to specified file
+    ///
+    /// The archive is written to temporary file near to destination and moved on success.
+    /// The destination is skipped when it is inside of source directory.
+    ///
+    /// # Errors
+    /// - Source directory cannot be read
+    /// - Destination cannot be written
+    pub fn to<D>(self, path: D) -> Result<Summary>
+    where
+        D: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let temp_path = sibling_temp_path(path, "roll")?;
+
+        let skip = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .canonicalize()
+            .map(|parent| {
+                [&temp_path, path]
+                    .iter()
+                    .filter_map(|path| Some(parent.join(path.file_name()?)))
+                    .collect::<Vec<_>>()
+            })?;
+
+        // Temporary file is created last, so it is removed on any failure
+        let file = File::create(&temp_path).context("create file", &temp_path)?;
+        let summary = self.roll(BufWriter::new(file), &skip).and_then(|summary| {
+            rename(&temp_path, path).context("rename", &temp_path)?;
+            Ok(summary)
+        });
+        if summary.is_err() {
+            let _ = remove_file(&temp_path);
+        }
+        summary
+    }
+
+    /// Write archive to writer
+    ///
+    /// # Errors
+    /// - Source directory cannot be read
+    /// - Writing failed
+    pub fn to_writer<W>(self, writer: W) -> Result<Summary>
+    where
+        W: Write,
+    {
+        self.roll(writer, &[])
+    }
+
+    fn roll<W>(mut self, writer: W, skip: &[PathBuf]) -> Result<Summary>
+    where
+        W: Write,
+    {
+        let started = Instant::now();
+        self.source = self.source.canonicalize().context("open", &self.source)?;
+
+        let mtime = flag!(self.flags[REPRODUCIBLE])
+            .then(|| source_date_epoch(|name| std::env::var(name).ok()));
+        let mut header = GzipHeaderBuilder::new();
+        if mtime.is_some() {
+            header.modification_time(0);
+        }
+
+        let written = Cell::new(0);
+        let writer = CountWriter {
+            inner: writer,
+            count: &written,
+        };
+        let encoder =
+            GzipEncoder::with_options(writer, EncodeOptions::new().header(header.finish()))?;
+        let mut builder = TarBuilder::new(encoder);
+        let mut entries = 0;
+
+        if let Some(prefix) = &self.prefix {
+            let metadata = self
+                .source
+                .metadata()
+                .context("read metadata of", &self.source)?;
+            let mut header = roll_header(&metadata, mtime);
+            builder.append_data(&mut header, prefix, std::io::empty())?;
+            entries += 1;
+        }
+
+        self.roll_dir(&mut builder, Path::new(""), skip, mtime, &mut entries)?;
+        builder.into_inner()?.finish().into_result()?.flush()?;
+
+        Ok(Summary {
+            bytes_written: written.get(),
+            entries,
+            elapsed: started.elapsed(),
+            ..Summary::default()
+        })
+    }
+
+    fn roll_dir<W>(
+        &self,
+        builder: &mut TarBuilder<W>,
+        relative: &Path,
+        skip: &[PathBuf],
+        mtime: Option<u64>,
+        entries: &mut usize,
+    ) -> Status
+    where
+        W: Write,
+    {
+        let dir = self.source.join(relative);
+        let mut dir_entries = dir
+            .read_dir()
+            .context("read directory", &dir)?
+            .collect::<IoResult<Vec<_>>>()?;
+        dir_entries.sort_by_key(std::fs::DirEntry::file_name);
+
+        for entry in dir_entries {
+            let path = entry.path();
+            let relative = relative.join(entry.file_name());
+            if skip.contains(&path)
+                || matches_patterns(&self.exclude_patterns, &relative)
+                || self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter(&relative))
+            {
+                continue;
+            }
+
+            let name = self
+                .prefix
+                .as_ref()
+                .map_or_else(|| relative.clone(), |prefix| prefix.join(&relative));
+            let metadata = path.symlink_metadata().context("read metadata of", &path)?;
+            let mut header = roll_header(&metadata, mtime);
+
+            if metadata.is_dir() {
+                builder.append_data(&mut header, &name, std::io::empty())?;
+                *entries += 1;
+                self.roll_dir(builder, &relative, skip, mtime, entries)?;
+            } else if metadata.is_file() {
+                let file = File::open(&path).context("open file", &path)?;
+                builder.append_data(&mut header, &name, file)?;
+                *entries += 1;
+            } else if metadata.file_type().is_symlink() {
+                let target = path.read_link().context("read link", &path)?;
+                builder.append_link(&mut header, &name, target)?;
+                *entries += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Make header of rolled entry
+///
+/// The deterministic metadata is used when modification time is set.
+fn roll_header(metadata: &std::fs::Metadata, mtime: Option<u64>) -> TarHeader {
+    let mut header = TarHeader::new_gnu();
+    if let Some(mtime) = mtime {
+        header.set_metadata_in_mode(metadata, HeaderMode::Deterministic);
+        header.set_mtime(mtime);
+    } else {
+        header.set_metadata_in_mode(metadata, HeaderMode::Complete);
+    }
+    header
+}
+
+/// Extract archive into staging directory and move it to destination on success
+fn unroll_archive_atomic<R>(source: R, options: &UnrollOptions, path: &Path) -> Result<Manifest>
+where
+    R: Read,
+{
+    if path.is_file() && !flag!(options.flags[FIX_INVALID_DEST]) {
+        return Err(IoError::new(
+            IoErrorKind::AlreadyExists,
+            format!("Destination `{}` is not a directory", path.display()),
+        )
+        .into());
+    }
+
+    if flag!(options.flags[CREATE_DEST_PATH]) {
+        if let Some(parent) = path.parent() {
+            create_dirs(parent, options.dir_mode)?;
+        }
+    }
+
+    let staging_path = sibling_temp_path(path, "staging")?;
+
+    if staging_path.exists() {
+        remove_dir_all(&staging_path).context("remove directory", &staging_path)?;
+    }
+    create_dirs(&staging_path, options.dir_mode)?;
+
+    let mut manifest = match unroll_archive_to(
+        source,
+        options,
+        &staging_path,
+        path,
+        &mut Progress::default(),
+        || Ok(()),
+    )
+    .and_then(|manifest| replace_path(&staging_path, path).map(|()| manifest))
+    {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            if staging_path.exists() {
+                remove_dir_all(&staging_path).context("remove directory", &staging_path)?;
+            }
+            return Err(error);
+        }
+    };
+
+    for entry in &mut manifest.entries {
+        if let Ok(entry_path) = entry.path.strip_prefix(&staging_path) {
+            entry.path = path.join(entry_path);
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Replace destination path by source path
+///
+/// The destination moved away before replacing and removed after.
+fn replace_path(source: &Path, destin: &Path) -> Status {
+    if destin.symlink_metadata().is_err() {
+        rename(source, destin).context("rename", source)?;
+        return Ok(());
+    }
+
+    let backup_path = sibling_temp_path(destin, "backup")?;
+    rename(destin, &backup_path).context("rename", destin)?;
+
+    if let Err(error) = rename(source, destin) {
+        // Try to restore original destination
+        rename(&backup_path, destin).context("rename", &backup_path)?;
+        return Err(error.into());
+    }
+
+    if backup_path.is_dir() {
+        remove_dir_all(&backup_path).context("remove directory", &backup_path)?;
+    } else {
+        remove_file(&backup_path).context("remove file", &backup_path)?;
+    }
+
+    Ok(())
+}
+
+/// Reader which writes passed data into temporary file
+///
+/// The file is moved to the final path on finish.
+struct TeeReader<R> {
+    inner: R,
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl<R> TeeReader<R> {
+    fn create(inner: R, path: &Path, options: &UnrollOptions) -> Result<Self> {
+        if flag!(options.flags[CREATE_DEST_PATH]) {
+            if let Some(parent) = path.parent() {
+                create_dirs(parent, options.dir_mode)?;
+            }
+        }
+
+        let temp_path = sibling_temp_path(path, "download")?;
+        let file = File::create(&temp_path).context("create file", &temp_path)?;
+
+        Ok(Self {
+            inner,
+            file,
+            temp_path,
+            path: path.into(),
+        })
+    }
+
+    /// Write the rest of data and move file to the final path
+    fn finish(mut self) -> Status
+    where
+        R: Read,
+    {
+        // Extraction may stop before the end of stream (like trailing zero blocks)
+        copy(&mut self.inner, &mut self.file)?;
+        drop(self.file);
+        replace_path(&self.temp_path, &self.path)
+    }
+
+    /// Remove incomplete file
+    fn abort(self) -> Status {
+        drop(self.file);
+        remove_file(&self.temp_path).context("remove file", &self.temp_path)?;
+        Ok(())
+    }
+
+    /// Keep file when hash mismatches or remove it otherwise
+    fn quarantine(self, error: &mut Error) -> Status {
+        if !matches!(error, Error::Checksum { .. }) {
+            return self.abort();
+        }
+        drop(self.file);
+        quarantine_file(&self.temp_path, &self.path, error)?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let len = self.inner.read(buf)?;
+        self.file.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+/// Extract archive into directory
+///
+/// The `prefix` is the final destination of extracted contents.
+fn unroll_archive_to<R, P>(
+    source: R,
+    options: &UnrollOptions,
+    destin: &Path,
+    prefix: &Path,
+    progress: &mut Progress,
+    prepare: P,
+) -> Result<Manifest>
+where
+    R: Read,
+    P: FnOnce() -> Status,
+{
+    let manifest = unroll_archive(source, options, |archive, layout| {
+        // Already extracted entries are reused when resuming
+        if flag!(options.flags[CHECK_FREE_SPACE]) && progress.resumed == 0 {
+            check_free_space(destin, layout.total_size)?;
+        }
+        prepare()?;
+        unroll_entries(archive, &layout, options, destin, progress)
+    })?;
+
+    if flag!(options.flags[FIX_PKGCONFIG]) || flag!(options.flags[FIX_LIBTOOL]) {
+        fix_prefixes(&manifest, destin, &std::path::absolute(prefix)?, options)?;
+    }
+
+    if let Some(after_unroll) = &options.after_unroll {
+        after_unroll(destin, &manifest)?;
+    }
+
+    log_record!(
+        info,
+        "Extracted {} entries into `{}`",
+        manifest.entries.len(),
+        prefix.display()
+    );
+
+    Ok(manifest)
+}
+
+/// Rewrite prefixes in pkg-config and libtool files to the actual destination
+fn fix_prefixes(
+    manifest: &Manifest,
+    destin: &Path,
+    prefix: &Path,
+    options: &UnrollOptions,
+) -> Status {
+    for path in manifest.files() {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+
+        let fixed = match extension {
+            Some("pc") if flag!(options.flags[FIX_PKGCONFIG]) => fix_lines(
+                path,
+                |line| line.starts_with("prefix="),
+                |_| format!("prefix={}", prefix_string(prefix)),
+            )?,
+            Some("la") if flag!(options.flags[FIX_LIBTOOL]) => {
+                let libdir = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(destin).ok())
+                    .map_or_else(|| prefix.into(), |parent| prefix.join(parent));
+                fix_lines(
+                    path,
+                    |line| line.starts_with("libdir="),
+                    |_| format!("libdir='{}'", prefix_string(&libdir)),
+                )?
+            }
+            _ => continue,
+        };
+
+        if let Some(contents) = fixed {
+            write(path, contents).context("write file", path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace matched lines of text file
+///
+/// Returns `None` when nothing replaced.
+fn fix_lines<M, F>(path: &Path, matches: M, fix: F) -> Result<Option<String>>
+where
+    M: Fn(&str) -> bool,
+    F: Fn(&str) -> String,
+{
+    let contents = read_to_string(path).context("read file", path)?;
+    let mut fixed = String::with_capacity(contents.len());
+    let mut replaced = false;
+
+    for line in contents.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        if matches(text) {
+            fixed.push_str(&fix(text));
+            fixed.push_str(&line[text.len()..]);
+            replaced = true;
+        } else {
+            fixed.push_str(line);
+        }
+    }
+
+    Ok(replaced.then_some(fixed))
+}
+
+/// Format path for pkg-config and libtool files
+///
+/// Backslashes are treated as escapes by tools so forward slashes are used.
+pub fn prefix_string(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.into_owned()
+    }
+}
+
+/// Remove stale entries and save the list of extracted entries for differential updates
+fn update_entries_record(record_path: &Path, dest_path: &Path, manifest: &Manifest) -> Status {
+    let entries = manifest
+        .entries
+        .iter()
+        .filter_map(|entry| entry.path.strip_prefix(dest_path).ok())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect::<HashSet<_>>();
+
+    if record_path.is_file() {
+        let record = read_to_string(record_path).context("read file", record_path)?;
+        let mut stale_entries = record
+            .lines()
+            .filter(|path| !entries.contains(*path))
+            .filter_map(|path| entry_path_stripped(Path::new(path), 0))
+            .filter(|path| path.iter().count() > 0)
+            .collect::<Vec<_>>();
+
+        // Directories should be removed after its contents
+        stale_entries.sort_by(|a, b| b.cmp(a));
+
+        for entry_path in stale_entries {
+            let path = dest_path.join(entry_path);
+            let Ok(metadata) = path.symlink_metadata() else {
+                continue;
+            };
+            if !metadata.is_dir() {
+                remove_file(&path).context("remove file", &path)?;
+            }
+            // Directories which contains foreign files are kept
+            for path in path
+                .ancestors()
+                .skip(usize::from(!metadata.is_dir()))
+                .take_while(|path| *path != dest_path)
+            {
+                if std::fs::remove_dir(path).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut record = String::new();
+    for path in &entries {
+        record.push_str(path);
+        record.push('\n');
+    }
+    write(record_path, record).context("write file", record_path)?;
+
+    Ok(())
+}
+
+/// Extraction progress record for resumable extraction
+#[derive(Default)]
+struct Progress {
+    /// Number of entries which was processed by previous extraction
+    resumed: usize,
+    /// Archive path of the last entry processed by previous extraction
+    resumed_path: String,
+    /// Number of processed entries
+    completed: usize,
+    /// Archive path of the last processed entry
+    last_path: String,
+}
+
+impl Progress {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        let record = read_to_string(path).context("read file", path)?;
+        let mut lines = record.lines();
+
+        let completed = lines
+            .next()
+            .and_then(|line| line.parse().ok())
+            .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "Invalid progress record"))?;
+        let last_path: String = lines.next().unwrap_or_default().into();
+
+        Ok(Self {
+            resumed: completed,
+            resumed_path: last_path.clone(),
+            completed,
+            last_path,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Status {
+        if self.completed > 0 {
+            write(path, format!("{}\n{}\n", self.completed, self.last_path))
+                .context("write file", path)?;
+        } else if path.is_file() {
+            remove_file(path).context("remove file", path)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the entry has been processed before
+    fn is_resumed<R>(&mut self, index: usize, entry: &TarEntry<R>) -> Result<bool>
+    where
+        R: Read,
+    {
+        if index + 1 == self.resumed
+            && String::from_utf8_lossy(&entry.path_bytes()) != self.resumed_path
+        {
+            // Stale record should be discarded
+            *self = Self::default();
+            return Err(known_failure(
+                ErrorKind::Extract,
+                "Archive does not match progress record",
+            )
+            .into());
+        }
+        Ok(index < self.resumed)
+    }
+
+    fn complete<R>(&mut self, index: usize, entry: &TarEntry<R>)
+    where
+        R: Read,
+    {
+        self.completed = index + 1;
+        self.last_path = String::from_utf8_lossy(&entry.path_bytes()).into();
+    }
+}
+
+fn unroll_archive<R, T, F>(source: R, options: &UnrollOptions, unroll: F) -> Result<T>
+where
+    R: Read,
+    F: FnOnce(&mut TarArchive<Box<dyn Read + '_>>, Layout) -> Result<T>,
+{
+    let mut decoder = Decoder::new(source, options)?;
+
+    if flag!(options.flags[STRIP_TOPLEVEL])
+        || options.strip_components > 0 && flag!(options.flags[STRIP_WHEN_ALONE])
+    {
+        let mut decoded_data = SpillBuffer::new(options);
+        let scan = {
+            trace_span!(
+                "decode",
+                bytes = tracing::field::Empty,
+                entries = tracing::field::Empty
+            );
+
+            // Entries are scanned while the decoded stream is staged in buffer
+            let mut tee = Tee {
+                reader: &mut decoder,
+                writer: &mut decoded_data,
+            };
+            let scan = scan_archive(&mut TarArchive::new(&mut tee))?;
+            copy(&mut tee, &mut sink())?;
+            decoder.drain()?;
+            trace_record!("bytes", decoded_data.len);
+            trace_record!("entries", scan.total_entries);
+
+            scan
+        };
+
+        let mut archive = open_archive(decoded_data.reader()?, options);
+        unroll(
+            &mut archive,
+            Layout {
+                strip_components: if flag!(options.flags[STRIP_WHEN_ALONE]) {
+                    let common_components = scan.common_components.saturating_sub(usize::from(
+                        scan.toplevel.is_some() && flag!(options.flags[STRIP_TOPLEVEL]),
+                    ));
+                    options.strip_components.min(common_components)
+                } else {
+                    options.strip_components
+                },
+                toplevel: scan
+                    .toplevel
+                    .filter(|_| flag!(options.flags[STRIP_TOPLEVEL])),
+                total_entries: Some(scan.total_entries),
+                total_size: Some(scan.total_size),
+            },
+        )
+    } else {
+        // Header of first entry is checked before extraction is started
+        let mut head = Vec::with_capacity(TAR_BLOCK_SIZE);
+        (&mut decoder)
+            .take(TAR_BLOCK_SIZE as u64)
+            .read_to_end(&mut head)?;
+        check_tar_header(&head)?;
+
+        let mut archive = open_archive(
+            Box::new(head.as_slice().chain(&mut decoder)) as Box<dyn Read>,
+            options,
+        );
+        let result = unroll(
+            &mut archive,
+            Layout {
+                strip_components: options.strip_components,
+                toplevel: None,
+                total_entries: None,
+                // Decoded size is at least the size of compressed data in practice
+                total_size: options.size_hint,
+            },
+        )?;
+        drop(archive);
+        decoder.drain()?;
+        Ok(result)
+    }
+}
+
+/// Size of tar header block
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Check that archive starts with valid tar header or end of archive
+fn check_tar_header(block: &[u8]) -> StdResult<(), IoError> {
+    // Empty archive is valid
+    if block.iter().all(|byte| *byte == 0) {
+        return Ok(());
+    }
+    let invalid = || {
+        decode_error(IoError::new(
+            IoErrorKind::InvalidData,
+            "Archive is not a valid tar",
+        ))
+    };
+    if block.len() < TAR_BLOCK_SIZE {
+        return Err(invalid());
+    }
+    // Checksum is computed as if its own field is filled by spaces
+    let checksum = block[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&block[156..TAR_BLOCK_SIZE])
+        .map(|byte| u32::from(*byte))
+        .sum::<u32>();
+    match tar::Header::from_byte_slice(block).cksum() {
+        Ok(expected) if expected == checksum => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Buffer of decoded archive which spills to temporary file when grows large
+pub struct SpillBuffer {
+    pub data: Vec<u8>,
+    pub file: Option<(BufWriter<File>, PathBuf)>,
+    pub len: u64,
+    threshold: u64,
+    buffer_size: usize,
+}
+
+impl SpillBuffer {
+    pub const fn new(options: &UnrollOptions) -> Self {
+        Self {
+            data: Vec::new(),
+            file: None,
+            len: 0,
+            threshold: if flag!(options.flags[BOUNDED_MEMORY]) {
+                0
+            } else {
+                options.spill_threshold
+            },
+            buffer_size: options.buffer_size,
+        }
+    }
+
+    /// Move buffered data to temporary file
+    fn spill(&mut self) -> IoResult<()> {
+        /// Number of spilled buffers used to make unique names
+        static SPILLED: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "fetch_unroll-{}-{}.tar",
+            std::process::id(),
+            SPILLED.fetch_add(1, Ordering::Relaxed)
+        ));
+        log_record!(debug, "Spilling decoded archive to `{}`", path.display());
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let file = BufWriter::with_capacity(self.buffer_size.max(1), file);
+        // Should be removed on errors too
+        let file = &mut self.file.insert((file, path)).0;
+
+        file.write_all(&self.data)?;
+        self.data = Vec::new();
+        Ok(())
+    }
+
+    /// Read data from start of buffer
+    pub fn reader(&mut self) -> Result<Box<dyn Read + '_>> {
+        Ok(match &mut self.file {
+            Some((file, _)) => {
+                file.flush()?;
+                let mut file = file.get_ref();
+                file.seek(SeekFrom::Start(0))?;
+                Box::new(BufReader::with_capacity(self.buffer_size.max(1), file))
+            }
+            None => Box::new(self.data.as_slice()),
+        })
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if self.file.is_none() && self.len + buf.len() as u64 > self.threshold {
+            self.spill()?;
+        }
+        let len = if let Some((file, _)) = &mut self.file {
+            file.write(buf)?
+        } else {
+            self.data.extend_from_slice(buf);
+            buf.len()
+        };
+        self.len += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.file.as_mut().map_or(Ok(()), |(file, _)| file.flush())
+    }
+}
+
+impl Drop for SpillBuffer {
+    fn drop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            // File should be closed before removing
+            drop(file.into_parts());
+            let _ = remove_file(path);
+        }
+    }
+}
+
+/// Reader which copies all read data into writer
+pub struct Tee<R, W> {
+    pub reader: R,
+    pub writer: W,
+}
+
+impl<R, W> Read for Tee<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let len = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+/// Archive layout info
+struct Layout {
+    /// Actual number of components to strip
+    strip_components: usize,
+    /// Top-level directory to strip
+    toplevel: Option<PathBuf>,
+    /// Number of entries when known before extraction
+    total_entries: Option<usize>,
+    /// Estimated size of files when known before extraction
+    total_size: Option<u64>,
+}
+
+fn unroll_entries_to_memory(
+    archive: &mut TarArchive<Box<dyn Read + '_>>,
+    layout: &Layout,
+    options: &UnrollOptions,
+) -> Result<HashMap<PathBuf, Vec<u8>>> {
+    let total_entries = layout.total_entries;
+
+    let mut usage = Usage::default();
+    let mut entries: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+
+    // Stripped archive path => Actual entry path (for resolving hard links)
+    let mut entry_paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for (index, entry) in archive.entries().map_err(decode_error)?.enumerate() {
+        let mut entry = entry.map_err(decode_error)?;
+        usage.account(&entry, options)?;
+
+        let type_ = entry.header().entry_type();
+
+        let Some(entry_path) = decode_entry_path(&entry.path_bytes(), options.non_utf8_names)?
+        else {
+            continue;
+        };
+
+        let Some((archive_path, entry_path)) =
+            resolve_entry_path(&entry_path, type_, layout, options)
+        else {
+            continue;
+        };
+
+        if !check_depth(&entry_path, options)? {
+            continue;
+        }
+
+        if let Some(on_entry) = &options.on_entry {
+            on_entry(index, total_entries, &entry_path);
+        }
+
+        let data = if is_file_entry(type_) {
+            let Some(data) = entry_contents(&mut entry, &entry_path, options)? else {
+                continue;
+            };
+            data
+        } else if type_ == TarEntryType::Link {
+            let link_path = link_target(&entry, layout, options, &entry_paths)?;
+            entries[link_path].clone()
+        } else {
+            if !type_.is_dir() && type_ != TarEntryType::Symlink {
+                unsupported_entry(&entry_path, type_, options)?;
+            }
+            continue;
+        };
+
+        entries.insert(entry_path.clone(), data);
+        entry_paths.insert(archive_path, entry_path);
+    }
+
+    Ok(entries)
+}
+
+fn unroll_entries(
+    archive: &mut TarArchive<Box<dyn Read + '_>>,
+    layout: &Layout,
+    options: &UnrollOptions,
+    dest_path: &Path,
+    progress: &mut Progress,
+) -> Result<Manifest> {
+    trace_span!(
+        "extract",
+        path = %dest_path.display(),
+        entries = tracing::field::Empty,
+        skipped = tracing::field::Empty
+    );
+
+    // Canonical destination is required to check entries.
+    // On Windows it also has `\\?\` prefix which allows paths longer than 260 characters.
+    let destin = dest_path.canonicalize()?;
+
+    let mut extraction = Extraction {
+        layout,
+        options,
+        dest_path,
+        names: EntryNames::new(options, &destin),
+        destin,
+        manifest: Manifest {
+            toplevel: layout
+                .toplevel
+                .as_ref()
+                .map(|toplevel| toplevel.to_string_lossy().into_owned()),
+            ..Manifest::default()
+        },
+        entry_paths: HashMap::new(),
+        files: FileWriter::new(options),
+        symlink_copies: Vec::new(),
+    };
+    let mut usage = Usage::default();
+    let mut failures = Vec::new();
+
+    // Directories are unpacked after all other entries (deepest first)
+    // to ensure that its permissions do not interfere with extraction.
+    let mut directories = Vec::new();
+
+    for (index, entry) in archive.entries().map_err(decode_error)?.enumerate() {
+        // Staged entries are not read from source which checks cancellation
+        if options
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            return Err(Error::Cancelled);
+        }
+        let entry = entry.map_err(decode_error)?;
+        usage.account(&entry, options)?;
+
+        let archive_path = flag!(options.flags[CONTINUE_ON_ERROR])
+            .then(|| PathBuf::from(String::from_utf8_lossy(&entry.path_bytes()).as_ref()));
+
+        match extraction.unroll_entry(index, entry, progress, &mut directories) {
+            // Limits violations and cancellation should abort extraction anyway
+            Err(error)
+                if archive_path.is_some()
+                    && !matches!(error, Error::Limit(_) | Error::Cancelled) =>
+            {
+                failures.push(EntryError {
+                    path: archive_path.unwrap_or_default(),
+                    error,
+                });
+            }
+            result => result?,
+        }
+    }
+
+    let Extraction {
+        destin,
+        mut manifest,
+        mut files,
+        symlink_copies,
+        ..
+    } = extraction;
+
+    files.wait()?;
+
+    for (link_path, target_path) in symlink_copies {
+        copy_symlink_target(&destin, &link_path, &target_path)?;
+    }
+
+    unroll_directories(directories, options, &destin, dest_path, &mut manifest)?;
+
+    if flag!(options.flags[REPRODUCIBLE]) {
+        let mtime =
+            UNIX_EPOCH + Duration::from_secs(source_date_epoch(|name| std::env::var(name).ok()));
+        normalize_tree(&destin, options, mtime)?;
+    }
+
+    trace_record!("entries", manifest.entries.len());
+    trace_record!("skipped", manifest.skipped.len());
+
+    if failures.is_empty() {
+        Ok(manifest)
+    } else {
+        Err(Error::Entries(failures))
+    }
+}
+
+/// State of extraction into directory
+struct Extraction<'a> {
+    layout: &'a Layout,
+    options: &'a UnrollOptions,
+    dest_path: &'a Path,
+    /// Canonical destination path
+    destin: PathBuf,
+    manifest: Manifest,
+    names: EntryNames,
+    /// Stripped archive path => Actual entry path (for resolving hard links)
+    entry_paths: HashMap<PathBuf, PathBuf>,
+    files: FileWriter,
+    /// Symlinks which should be replaced by copies of targets (link path, target path)
+    symlink_copies: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Extraction<'_> {
+    /// Report skipped entry
+    fn skip<R>(&mut self, entry: &TarEntry<R>, reason: SkipReason)
+    where
+        R: Read,
+    {
+        let path = PathBuf::from(String::from_utf8_lossy(&entry.path_bytes()).as_ref());
+        log_record!(debug, "Skipping entry `{}`: {reason:?}", path.display());
+        if matches!(
+            reason,
+            SkipReason::NonUtf8Name | SkipReason::WindowsName | SkipReason::TooDeep
+        ) {
+            cargo_warning(format_args!(
+                "Skipped entry `{}`: {reason:?}",
+                path.display()
+            ));
+        }
+        self.manifest.skipped.push(SkippedEntry { path, reason });
+    }
+
+    /// Extract single entry
+    ///
+    /// Directories are deferred to be unpacked after all other entries.
+    fn unroll_entry<'e, R>(
+        &mut self,
+        index: usize,
+        mut entry: TarEntry<'e, R>,
+        progress: &mut Progress,
+        directories: &mut Vec<(PathBuf, TarEntry<'e, R>)>,
+    ) -> Status
+    where
+        R: Read,
+    {
+        let type_ = entry.header().entry_type();
+
+        let Some(entry_path) = decode_entry_path(&entry.path_bytes(), self.options.non_utf8_names)?
+        else {
+            self.skip(&entry, SkipReason::NonUtf8Name);
+            return Ok(());
+        };
+
+        let Some((archive_path, entry_path)) =
+            resolve_entry_path(&entry_path, type_, self.layout, self.options)
+        else {
+            // Stripped directories and global headers are not interesting
+            if type_.is_dir() || type_ == TarEntryType::XGlobalHeader {
+                return Ok(());
+            }
+            self.skip(&entry, SkipReason::Filtered);
+            return Ok(());
+        };
+
+        if !check_depth(&entry_path, self.options)? {
+            self.skip(&entry, SkipReason::TooDeep);
+            return Ok(());
+        }
+
+        let Some(entry_path) = windows_entry_path(entry_path, self.options.windows_names)? else {
+            self.skip(&entry, SkipReason::WindowsName);
+            return Ok(());
+        };
+
+        let entry_path = self.names.resolve(entry_path, type_.is_dir())?;
+
+        log_record!(debug, "Extracting entry `{}`", entry_path.display());
+
+        if let Some(on_entry) = &self.options.on_entry {
+            on_entry(index, self.layout.total_entries, &entry_path);
+        }
+
+        if type_.is_dir() {
+            directories.push((entry_path, entry));
+            return Ok(());
+        }
+
+        let entry_dest_path = dest_path_inside(&self.destin, &entry_path, self.options.dir_mode)?;
+
+        let is_resumed = progress.is_resumed(index, &entry)?;
+
+        if is_resumed || !should_overwrite(&entry, &entry_dest_path, self.options.overwrite)? {
+            progress.complete(index, &entry);
+            self.entry_paths.insert(archive_path, entry_path);
+            if is_resumed {
+                return Ok(());
+            }
+            self.skip(&entry, SkipReason::AlreadyExists);
+            return Ok(());
+        }
+
+        // Entries other than files may depend on files which are being written
+        if !is_file_entry(type_) {
+            self.files.wait()?;
+        }
+
+        let (kind, size) = match type_ {
+            TarEntryType::Link => {
+                let link_path = link_target(&entry, self.layout, self.options, &self.entry_paths)?;
+                let link_path = dest_path_inside(&self.destin, link_path, self.options.dir_mode)?;
+
+                if entry_dest_path.symlink_metadata().is_ok() {
+                    remove_file(&entry_dest_path).context("remove file", &entry_dest_path)?;
+                }
+                hard_link(link_path, &entry_dest_path).context("create link", &entry_dest_path)?;
+                (EntryKind::HardLink, entry_dest_path.metadata()?.len())
+            }
+            TarEntryType::Symlink => {
+                match entry.unpack(&entry_dest_path) {
+                    Err(_) if self.options.symlink_fallback == SymlinkFallback::Copy => {
+                        let target_path = entry.link_name()?.ok_or_else(|| {
+                            known_failure(ErrorKind::Extract, "Invalid symlink target")
+                        })?;
+                        self.symlink_copies
+                            .push((entry_dest_path.clone(), target_path.into_owned()));
+                    }
+                    result => {
+                        result?;
+                    }
+                }
+                (EntryKind::Symlink, entry.size())
+            }
+            type_ if is_file_entry(type_) => {
+                let Some(size) =
+                    self.files
+                        .unpack(&mut entry, &entry_path, &entry_dest_path, self.options)?
+                else {
+                    self.skip(&entry, SkipReason::Filtered);
+                    return Ok(());
+                };
+                (EntryKind::File, size)
+            }
+            _ => {
+                unsupported_entry(&entry_path, type_, self.options)?;
+                self.skip(&entry, SkipReason::Unsupported);
+                return Ok(());
+            }
+        };
+
+        if kind != EntryKind::File {
+            apply_ownership(&entry_dest_path, self.options.ownership)?;
+        }
+
+        self.manifest.push(ManifestEntry {
+            path: self.dest_path.join(&entry_path),
+            size,
+            kind,
+        });
+        progress.complete(index, &entry);
+        self.entry_paths.insert(archive_path, entry_path);
+
+        Ok(())
+    }
+}
+
+/// Get timestamp for reproducible builds from `SOURCE_DATE_EPOCH`
+pub fn source_date_epoch<F>(lookup: F) -> u64
+where
+    F: Fn(&str) -> Option<String>,
+{
+    const NAME: &str = "SOURCE_DATE_EPOCH";
+
+    rerun_if_env_changed(NAME);
+    parse_env(lookup, NAME).unwrap_or_default()
+}
+
+/// Apply fixed modes and modification times to extracted tree
+///
+/// Symlinks are left as is.
+fn normalize_tree(path: &Path, options: &UnrollOptions, mtime: SystemTime) -> Status {
+    for entry in path.read_dir().context("read directory", path)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            normalize_tree(&path, options, mtime)?;
+            set_dir_mode(&path, options.dir_mode.unwrap_or(0o755))
+                .context("set permissions of", &path)?;
+            #[cfg(unix)]
+            File::open(&path)
+                .and_then(|dir| dir.set_modified(mtime))
+                .context("set modification time of", &path)?;
+        } else if file_type.is_file() {
+            let executable = is_executable_file(&entry.metadata()?);
+            let mode = if executable { 0o755 } else { 0o644 };
+            let file = File::open(&path).context("open file", &path)?;
+            set_file_mode(&file, mode).context("set permissions of", &path)?;
+            file.set_modified(mtime)
+                .context("set modification time of", &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable_file(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+const fn is_executable_file(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Replace symlink by the copy of its target
+///
+/// The target should be inside of destination.
+pub fn copy_symlink_target(destin: &Path, link_path: &Path, target_path: &Path) -> Status {
+    let target_path = link_path
+        .parent()
+        .unwrap_or(destin)
+        .join(target_path)
+        .canonicalize()?;
+
+    // Copying of ancestor directory into itself never ends
+    if !target_path.starts_with(destin) || link_path.starts_with(&target_path) {
+        return Err(known_failure(
+            ErrorKind::Extract,
+            format!("Symlink `{}` target cannot be copied", link_path.display()),
+        )
+        .into());
+    }
+
+    if link_path.symlink_metadata().is_ok() {
+        remove_file(link_path).context("remove file", link_path)?;
+    }
+
+    copy_path(&target_path, link_path)?;
+
+    Ok(())
+}
+
+/// Copy file or directory recursively
+fn copy_path(source: &Path, destin: &Path) -> StdResult<(), IoError> {
+    if source.is_dir() {
+        create_dir_all(destin).context("create directory", destin)?;
+        for entry in source.read_dir()? {
+            let entry = entry?;
+            copy_path(&entry.path(), &destin.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(source, destin)?;
+    }
+    Ok(())
+}
+
+/// Unpack deferred directory entries (deepest first)
+fn unroll_directories<R>(
+    mut directories: Vec<(PathBuf, TarEntry<R>)>,
+    options: &UnrollOptions,
+    destin: &Path,
+    dest_path: &Path,
+    manifest: &mut Manifest,
+) -> Status
+where
+    R: Read,
+{
+    directories.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    for (entry_path, mut entry) in directories {
+        let entry_dest_path = dest_path_inside(destin, &entry_path, options.dir_mode)?;
+        entry.unpack(&entry_dest_path)?;
+        if let Some(mode) = options.dir_mode {
+            set_dir_mode(&entry_dest_path, mode)?;
+        }
+        apply_ownership(&entry_dest_path, options.ownership)?;
+
+        manifest.push(ManifestEntry {
+            path: dest_path.join(&entry_path),
+            size: 0,
+            kind: EntryKind::Directory,
+        });
+    }
+
+    Ok(())
+}
+
+/// Handle entry of unsupported type according to policy
+fn unsupported_entry(entry_path: &Path, type_: TarEntryType, options: &UnrollOptions) -> Status {
+    let message = || {
+        format!(
+            "Entry `{}` has unsupported type {type_:?}",
+            entry_path.display()
+        )
+    };
+
+    match options.unsupported_entries {
+        UnsupportedEntries::Skip => Ok(()),
+        UnsupportedEntries::Warn => {
+            log_record!(warn, "{}", message());
+            cargo_warning(message());
+            if let Some(on_warning) = &options.on_warning {
+                on_warning(&message());
+            }
+            Ok(())
+        }
+        UnsupportedEntries::Error => Err(IoError::new(IoErrorKind::Unsupported, message()).into()),
+    }
+}
+
+/// Decode entry path from archive according to non-UTF8 names policy
+///
+/// Returns `None` when entry should be skipped.
+pub fn decode_entry_path(path: &[u8], policy: NonUtf8Names) -> Result<Option<PathBuf>> {
+    if let Ok(path) = std::str::from_utf8(path) {
+        return Ok(Some(PathBuf::from(path)));
+    }
+
+    match policy {
+        NonUtf8Names::Lossy => Ok(Some(PathBuf::from(String::from_utf8_lossy(path).as_ref()))),
+        NonUtf8Names::PercentEncode => Ok(Some(PathBuf::from(percent_encode(path)))),
+        NonUtf8Names::Skip => Ok(None),
+        NonUtf8Names::Error => Err(known_failure(
+            ErrorKind::Extract,
+            format!(
+                "Entry `{}` has non-UTF8 name",
+                String::from_utf8_lossy(path)
+            ),
+        )
+        .into()),
+    }
+}
+
+/// Encode non-ASCII bytes and percent sign as `%XX`
+fn percent_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len() * 3);
+
+    for &byte in data {
+        if byte.is_ascii() && byte != b'%' {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+
+    encoded
+}
+
+/// Resolve actual path of hard link target
+///
+/// The target should be extracted before.
+fn link_target<'a, R>(
+    entry: &TarEntry<R>,
+    layout: &Layout,
+    options: &UnrollOptions,
+    entry_paths: &'a HashMap<PathBuf, PathBuf>,
+) -> Result<&'a PathBuf>
+where
+    R: Read,
+{
+    entry
+        .link_name_bytes()
+        .map(|link_path| decode_entry_path(&link_path, options.non_utf8_names))
+        .transpose()?
+        .flatten()
+        .and_then(|link_path| strip_entry_path(&link_path, false, layout, options))
+        .and_then(|link_path| entry_paths.get(&link_path))
+        .ok_or_else(|| known_failure(ErrorKind::Extract, "Invalid hard link target").into())
+}
+
+/// Resolve path of entry relative to destination according to options
+///
+/// Returns both stripped archive path and resulting entry path
+/// or `None` when entry should be skipped.
+fn resolve_entry_path(
+    entry_path: &Path,
+    type_: TarEntryType,
+    layout: &Layout,
+    options: &UnrollOptions,
+) -> Option<(PathBuf, PathBuf)> {
+    // Global PAX headers has no meaningful data for extraction
+    if type_ == TarEntryType::XGlobalHeader {
+        return None;
+    }
+
+    if let Some(kind) = EntryKind::from_entry_type(type_) {
+        if options.skip_kinds & kind.mask() != 0 {
+            return None;
+        }
+    }
+
+    let archive_path = strip_entry_path(entry_path, type_.is_dir(), layout, options)?;
+
+    let entry_path = if let Some(map_path) = &options.map_path {
+        map_path(&archive_path)
+            .and_then(|path| entry_path_stripped(&path, 0))
+            .filter(|path| path.iter().count() > 0)?
+    } else {
+        archive_path.clone()
+    };
+
+    let entry_path = if flag!(options.flags[FLATTEN_ENTRIES]) {
+        if !is_file_entry(type_) && type_ != TarEntryType::Link {
+            return None;
+        }
+        PathBuf::from(entry_path.file_name()?)
+    } else {
+        entry_path
+    };
+
+    Some((archive_path, entry_path))
+}
+
+/// Check depth of entry path according to options
+///
+/// Returns `false` when entry should be skipped.
+fn check_depth(entry_path: &Path, options: &UnrollOptions) -> Result<bool> {
+    match options.max_depth {
+        Some(max_depth) if entry_path.iter().count() > max_depth => {
+            if flag!(options.flags[SKIP_TOO_DEEP]) {
+                Ok(false)
+            } else {
+                Err(Error::Limit(format!(
+                    "Depth of entry `{}` is greater than {max_depth}",
+                    entry_path.display()
+                )))
+            }
+        }
+        _ => Ok(true),
+    }
+}
+
+/// Check that entry has file contents
+const fn is_file_entry(type_: TarEntryType) -> bool {
+    matches!(
+        type_,
+        TarEntryType::Regular | TarEntryType::Continuous | TarEntryType::GNUSparse
+    )
+}
+
+/// Strip leading part of entry path according to options
+///
+/// Returns `None` when entry should be skipped.
+fn strip_entry_path(
+    entry_path: &Path,
+    is_dir: bool,
+    layout: &Layout,
+    options: &UnrollOptions,
+) -> Option<PathBuf> {
+    let toplevel_stripped_path;
+
+    let entry_path = if let Some(toplevel) = &layout.toplevel {
+        toplevel_stripped_path = entry_path_stripped(entry_path, 0)?
+            .strip_prefix(toplevel)
+            .ok()?
+            .to_path_buf();
+        &toplevel_stripped_path
+    } else {
+        entry_path
+    };
+
+    let prefix_stripped_path;
+
+    let entry_path = if let Some(prefix) = &options.strip_prefix {
+        prefix_stripped_path = entry_path_stripped(entry_path, 0)?
+            .strip_prefix(prefix)
+            .ok()?
+            .to_path_buf();
+        &prefix_stripped_path
+    } else {
+        entry_path
+    };
+
+    // Files never stripped entirely
+    let strip_components = if is_dir {
+        layout.strip_components
+    } else {
+        layout
+            .strip_components
+            .min(entry_path.iter().count().saturating_sub(1))
+    };
+
+    entry_path_stripped(entry_path, strip_components).filter(|path| path.iter().count() > 0)
+}
+
+/// Strip leading components of entry path
+///
+/// Returns `None` for paths which refers to parent directories.
+fn entry_path_stripped(entry_path: &Path, strip_components: usize) -> Option<PathBuf> {
+    let mut stripped_path = PathBuf::new();
+
+    for component in Path::new(
+        &entry_path
+            .iter()
+            .skip(strip_components)
+            .collect::<PathBuf>(),
+    )
+    .components()
+    {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => return None,
+            Component::Normal(part) => stripped_path.push(part),
+        }
+    }
+
+    Some(stripped_path)
+}
+
+/// Check whether the entry should be written over existing file
+fn should_overwrite<R>(entry: &TarEntry<R>, dest_path: &Path, policy: Overwrite) -> Result<bool>
+where
+    R: Read,
+{
+    let Ok(metadata) = dest_path.symlink_metadata() else {
+        // Nothing to overwrite
+        return Ok(true);
+    };
+
+    match policy {
+        Overwrite::Always => Ok(true),
+        Overwrite::Never => Ok(false),
+        Overwrite::IfNewer => {
+            let entry_mtime = entry.header().mtime()?;
+            let dest_mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            Ok(entry_mtime > dest_mtime)
+        }
+        Overwrite::IfChanged => {
+            if !is_file_entry(entry.header().entry_type()) || !metadata.is_file() {
+                return Ok(true);
+            }
+            // Zero mtime is avoided on extraction
+            let entry_mtime = entry.header().mtime()?.max(1);
+            let dest_mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            let same_size = entry.size() == metadata.len();
+            Ok(entry_mtime != dest_mtime || !same_size)
+        }
+        Overwrite::Error => Err(IoError::new(
+            IoErrorKind::AlreadyExists,
+            format!("Entry `{}` already exists", dest_path.display()),
+        )
+        .into()),
+    }
+}
+
+/// Characters which cannot be used in file names on Windows
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names which cannot be used as file names on Windows (even with extension)
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Check entry path for names which are invalid on Windows
+///
+/// Does nothing on other platforms.
+fn windows_entry_path(entry_path: PathBuf, policy: WindowsNames) -> Result<Option<PathBuf>> {
+    if !cfg!(windows) {
+        return Ok(Some(entry_path));
+    }
+
+    match sanitize_windows_path(&entry_path) {
+        None => Ok(Some(entry_path)),
+        Some(sanitized_path) => match policy {
+            WindowsNames::Error => Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                format!(
+                    "Entry `{}` has invalid name for Windows",
+                    entry_path.display()
+                ),
+            )
+            .into()),
+            WindowsNames::Rename => Ok(Some(sanitized_path)),
+            WindowsNames::Skip => Ok(None),
+        },
+    }
+}
+
+/// Make path valid for Windows
+///
+/// Returns `None` when path already valid.
+pub fn sanitize_windows_path(path: &Path) -> Option<PathBuf> {
+    let mut changed = false;
+
+    let sanitized_path = path
+        .iter()
+        .map(|name| {
+            let name = name.to_string_lossy();
+            let sanitized_name = sanitize_windows_name(&name);
+            changed |= sanitized_name != name;
+            sanitized_name
+        })
+        .collect();
+
+    if changed {
+        Some(sanitized_path)
+    } else {
+        None
+    }
+}
+
+fn sanitize_windows_name(name: &str) -> String {
+    let mut name = name
+        .chars()
+        .map(|chr| {
+            if chr.is_control() || WINDOWS_INVALID_CHARS.contains(&chr) {
+                '_'
+            } else {
+                chr
+            }
+        })
+        .collect::<String>();
+
+    // Trailing dots and spaces are silently removed by Windows
+    let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+    if trimmed_len < name.len() {
+        name.replace_range(trimmed_len.., &"_".repeat(name.len() - trimmed_len));
+    }
+
+    let stem_len = name.find('.').unwrap_or(name.len());
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(&name[..stem_len]))
+    {
+        name.insert(stem_len, '_');
+    }
+
+    name
+}
+
+/// Extracted entry names tracker
+///
+/// Detects entries which names differs only by case on case-insensitive filesystems.
+pub struct EntryNames {
+    // Lowercased path => (original path, is directory)
+    pub names: HashMap<String, (PathBuf, bool)>,
+    pub policy: CaseCollision,
+}
+
+impl EntryNames {
+    fn new(options: &UnrollOptions, destin: &Path) -> Self {
+        let policy =
+            if options.case_collision != CaseCollision::Ignore && is_case_insensitive(destin) {
+                options.case_collision
+            } else {
+                CaseCollision::Ignore
+            };
+
+        Self {
+            names: HashMap::new(),
+            policy,
+        }
+    }
+
+    pub fn resolve(&mut self, entry_path: PathBuf, is_dir: bool) -> Result<PathBuf> {
+        if self.policy == CaseCollision::Ignore {
+            return Ok(entry_path);
+        }
+
+        let name = entry_path.to_string_lossy().to_lowercase();
+
+        match self.names.get(&name) {
+            None => {
+                self.names.insert(name, (entry_path.clone(), is_dir));
+                Ok(entry_path)
+            }
+            // The same entry or directories which will be merged
+            Some((path, path_is_dir)) if path == &entry_path || (is_dir && *path_is_dir) => {
+                Ok(entry_path)
+            }
+            Some((path, _)) => {
+                if self.policy == CaseCollision::Error {
+                    return Err(IoError::new(
+                        IoErrorKind::AlreadyExists,
+                        format!(
+                            "Entry `{}` collides with `{}`",
+                            entry_path.display(),
+                            path.display()
+                        ),
+                    )
+                    .into());
+                }
+
+                let mut suffix = 1usize;
+                loop {
+                    let mut file_name = entry_path.file_name().unwrap_or_default().to_owned();
+                    file_name.push(format!("~{suffix}"));
+                    let renamed_path = entry_path.with_file_name(file_name);
+                    let name = renamed_path.to_string_lossy().to_lowercase();
+
+                    if let MapEntry::Vacant(entry) = self.names.entry(name) {
+                        entry.insert((renamed_path.clone(), is_dir));
+                        return Ok(renamed_path);
+                    }
+                    suffix += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Check whether filesystem of path is case-insensitive
+///
+/// Looks up the nearest ancestor with case-sensitive name using swapped case,
+/// so nothing is written into destination.
+/// The platform default is used when there is no such ancestor.
+pub fn is_case_insensitive(path: &Path) -> bool {
+    for ancestor in path.ancestors() {
+        let Some(name) = ancestor.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let swapped = name
+            .chars()
+            .map(|c| {
+                if c.is_lowercase() {
+                    c.to_ascii_uppercase()
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect::<String>();
+        if swapped != name {
+            return is_same_file(ancestor, &ancestor.with_file_name(swapped));
+        }
+    }
+    cfg!(any(windows, target_os = "macos"))
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, b: &Path) -> bool {
+    b.exists()
+}
+
+/// Make destination path for entry and ensure that it lies inside of destination directory
+///
+/// Parent directories will be created when needed.
+fn dest_path_inside(destin: &Path, entry_path: &Path, dir_mode: Option<u32>) -> Result<PathBuf> {
+    let dest_path = destin.join(entry_path);
+
+    if let Some(parent) = dest_path.parent() {
+        create_dirs(parent, dir_mode)?;
+
+        // Symlinks extracted before may point to outside of destination
+        if !parent.canonicalize()?.starts_with(destin) {
+            return Err(known_failure(
+                ErrorKind::Extract,
+                format!(
+                    "Entry `{}` is outside of destination directory",
+                    entry_path.display()
+                ),
+            )
+            .into());
+        }
+    }
+
+    Ok(dest_path)
+}
+
+/// Create directory with all missing parents applying directory mode
+fn create_dirs(path: &Path, mode: Option<u32>) -> StdResult<(), IoError> {
+    let Some(mode) = mode else {
+        return create_dir_all(path).context("create directory", path);
+    };
+
+    let missing_paths = path
+        .ancestors()
+        .take_while(|path| !path.as_os_str().is_empty() && !path.exists())
+        .collect::<Vec<_>>();
+
+    create_dir_all(path).context("create directory", path)?;
+
+    for path in missing_paths {
+        set_dir_mode(path, mode).context("set permissions of", path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_dir_mode(path: &Path, mode: u32) -> StdResult<(), IoError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn set_dir_mode(_path: &Path, _mode: u32) -> StdResult<(), IoError> {
+    Ok(())
+}
+
+/// Size of decompressed data which should be reached before checking compression ratio
+const RATIO_CHECK_THRESHOLD: u64 = 1 << 20;
+
+/// Archive decoder
+///
+/// Keeps track of compressed and decompressed sizes to detect suspicious compression ratios.
+struct Decoder<R> {
+    inner: DecoderInner<R>,
+    decoded: u64,
+    max_ratio: Option<u64>,
+}
+
+/// Decompression in current or separate thread
+enum DecoderInner<R> {
+    Inline(GzipDecoder<Counter<R>>),
+    Threaded(ThreadedDecoder<Counter<R>>),
+}
+
+impl<R> Decoder<R>
+where
+    R: Read,
+{
+    fn new(source: R, options: &UnrollOptions) -> StdResult<Self, IoError> {
+        let source = Counter::new(source);
+        Ok(Self {
+            inner: if flag!(options.flags[THREADED_DECODE]) {
+                DecoderInner::Threaded(ThreadedDecoder::new(source, options.buffer_size))
+            } else {
+                DecoderInner::Inline(GzipDecoder::new(source).map_err(decode_error)?)
+            },
+            decoded: 0,
+            max_ratio: options.max_compression_ratio,
+        })
+    }
+
+    /// Read the rest of data after end of archive
+    ///
+    /// The whole source should be consumed, like to verify its hash.
+    fn drain(&mut self) -> IoResult<()> {
+        copy(self, &mut sink())?;
+        match &mut self.inner {
+            DecoderInner::Inline(decoder) => copy(decoder.as_inner_mut(), &mut sink()),
+            DecoderInner::Threaded(decoder) => copy(&mut decoder.source, &mut sink()),
+        }?;
+        Ok(())
+    }
+
+    /// Number of bytes read from source
+    fn encoded(&self) -> u64 {
+        match &self.inner {
+            DecoderInner::Inline(decoder) => decoder.as_inner_ref().count,
+            DecoderInner::Threaded(decoder) => decoder.source.count,
+        }
+    }
+}
+
+impl<R> Read for Decoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> StdResult<usize, IoError> {
+        let len = match &mut self.inner {
+            DecoderInner::Inline(decoder) => decoder.read(buf),
+            DecoderInner::Threaded(decoder) => decoder.read(buf),
+        }
+        .map_err(decode_error)?;
+        self.decoded += len as u64;
+
+        if let Some(max_ratio) = self.max_ratio {
+            let encoded = self.encoded().max(1);
+
+            if self.decoded >= RATIO_CHECK_THRESHOLD && self.decoded / encoded > max_ratio {
+                return Err(IoError::other(LimitExceeded(format!(
+                    "Compression ratio {} is greater than {max_ratio}",
+                    self.decoded / encoded
+                ))));
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Decoder which decompresses data in separate thread
+///
+/// The source is read in current thread because it may be not `Send`.
+/// The decoding thread reports each taken chunk of compressed data,
+/// so the current thread blocks only on receiving from it and passes
+/// next chunks of source on request.
+///
+/// It's a pipelining of reading and decompression of single stream,
+/// not a parallel decompression of its blocks.
+pub struct ThreadedDecoder<R> {
+    source: R,
+    /// Sender of compressed chunks, it's dropped at end of source
+    input: Option<SyncSender<Vec<u8>>>,
+    /// Receiver of messages from decoding thread
+    output: Receiver<Decoded>,
+    /// Number of compressed chunks which can be passed without blocking
+    requested: usize,
+    chunk: Vec<u8>,
+    offset: usize,
+    buffer_size: usize,
+    /// The end of data is received
+    finished: bool,
+}
+
+/// Message from decoding thread
+pub enum Decoded {
+    /// Chunk of decompressed data, the empty chunk means end of data
+    Data(IoResult<Vec<u8>>),
+    /// Chunk of compressed data is taken, so the next one can be passed
+    Taken,
+}
+
+impl From<IoResult<Vec<u8>>> for Decoded {
+    fn from(result: IoResult<Vec<u8>>) -> Self {
+        Self::Data(result)
+    }
+}
+
+/// Reader of compressed chunks in decoding thread
+///
+/// The disconnection of sender means end of data.
+pub struct FedReader {
+    input: Receiver<Vec<u8>>,
+    output: SyncSender<Decoded>,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for FedReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.offset >= self.chunk.len() {
+            let Ok(chunk) = self.input.recv() else {
+                return Ok(0);
+            };
+            // Receiver is gone only when decoded data isn't needed anymore
+            let _ = self.output.send(Decoded::Taken);
+            self.chunk = chunk;
+            self.offset = 0;
+        }
+        let len = buf.len().min(self.chunk.len() - self.offset);
+        buf[..len].copy_from_slice(&self.chunk[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl<R> ThreadedDecoder<R> {
+    /// Number of chunks in flight per channel
+    const CHUNKS: usize = 4;
+
+    fn new(source: R, buffer_size: usize) -> Self {
+        Self::spawn(source, buffer_size, GzipDecoder::new)
+    }
+
+    /// Run decoder which is made by `decode` in separate thread
+    pub fn spawn<F, D>(source: R, buffer_size: usize, decode: F) -> Self
+    where
+        F: FnOnce(FedReader) -> IoResult<D> + Send + 'static,
+        D: Read,
+    {
+        let buffer_size = buffer_size.max(1);
+        let (input, input_receiver) = sync_channel(Self::CHUNKS);
+        let (output_sender, output) = sync_channel(Self::CHUNKS);
+
+        spawn(move || {
+            let reader = FedReader {
+                input: input_receiver,
+                output: output_sender.clone(),
+                chunk: Vec::new(),
+                offset: 0,
+            };
+            match decode(reader) {
+                Ok(decoder) => send_chunks(decoder, &output_sender, buffer_size),
+                Err(error) => {
+                    let _ = output_sender.send(Decoded::Data(Err(error)));
+                }
+            }
+        });
+
+        Self {
+            source,
+            input: Some(input),
+            output,
+            requested: Self::CHUNKS,
+            chunk: Vec::new(),
+            offset: 0,
+            buffer_size,
+            finished: false,
+        }
+    }
+}
+
+impl<R> ThreadedDecoder<R>
+where
+    R: Read,
+{
+    /// Pass next compressed chunk to decoding thread
+    ///
+    /// It never blocks because no more chunks are passed than taken.
+    fn feed(&mut self) -> IoResult<()> {
+        let Some(input) = &self.input else {
+            return Ok(());
+        };
+        let mut chunk = vec![0; self.buffer_size];
+        let len = self.source.read(&mut chunk)?;
+        chunk.truncate(len);
+        // Decoding may finish before end of source
+        if chunk.is_empty() || input.send(chunk).is_err() {
+            self.input = None;
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for ThreadedDecoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        while self.offset >= self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            // Decoding thread may need more input before it outputs anything
+            while self.requested > 0 && self.input.is_some() {
+                self.feed()?;
+                self.requested -= 1;
+            }
+            // Decoding thread always sends the end of data before exit,
+            // so it has panicked when the channel is closed without it
+            let message = self
+                .output
+                .recv()
+                .map_err(|_| IoError::other("Decoding thread stopped unexpectedly"))?;
+            let Decoded::Data(result) = message else {
+                self.requested += 1;
+                continue;
+            };
+            self.chunk = result?;
+            self.offset = 0;
+            if self.chunk.is_empty() {
+                self.finished = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.offset);
+        buf[..len].copy_from_slice(&self.chunk[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+/// Reader which counts bytes passed through
+struct Counter<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> Counter<R> {
+    const fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R> Read for Counter<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> StdResult<usize, IoError> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// Accumulated archive usage for limits checking
+#[derive(Default)]
+struct Usage {
+    entries: usize,
+    size: u64,
+}
+
+impl Usage {
+    fn account<R>(&mut self, entry: &TarEntry<R>, options: &UnrollOptions) -> Status
+    where
+        R: Read,
+    {
+        self.entries += 1;
+        self.size += entry.size();
+
+        if let Some(max_entries) = options.max_entries {
+            if self.entries > max_entries {
+                return Err(Error::Limit(format!(
+                    "Number of entries is greater than {max_entries}"
+                )));
+            }
+        }
+
+        if let Some(max_size) = options.max_entry_size {
+            if entry.size() > max_size {
+                return Err(Error::Limit(format!(
+                    "Size of entry `{}` is greater than {max_size} bytes",
+                    String::from_utf8_lossy(&entry.path_bytes())
+                )));
+            }
+        }
+
+        if let Some(max_size) = options.max_unpacked_size {
+            if self.size > max_size {
+                return Err(Error::Limit(format!(
+                    "Unpacked size is greater than {max_size} bytes"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn open_archive<R>(source: R, options: &UnrollOptions) -> TarArchive<R>
+where
+    R: Read,
+{
+    let mut archive = TarArchive::new(source);
+    archive.set_unpack_xattrs(flag!(options.flags[UNPACK_XATTRS]));
+    archive.set_preserve_ownerships(options.ownership == Ownership::Privileged && is_privileged());
+    archive
+}
+
+#[cfg(unix)]
+pub fn is_privileged() -> bool {
+    // SAFETY: `geteuid` is always successful and has no side effects
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub const fn is_privileged() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn apply_ownership(path: &Path, ownership: Ownership) -> Status {
+    if ownership == Ownership::Current {
+        // SAFETY: `geteuid` and `getegid` are always successful and have no side effects
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        lchown(path, Some(uid), Some(gid))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn apply_ownership(_path: &Path, _ownership: Ownership) -> Status {
+    Ok(())
+}
+
+/// Size, mode and hash of file contents
+type ContentsKey = (u64, Option<u32>, u64);
+
+/// Writer of extracted files
+struct FileWriter {
+    workers: Option<Workers>,
+    /// Skip files which contents is not changed
+    update: bool,
+    /// Contents key => Path of the first file with such contents
+    duplicates: Option<HashMap<ContentsKey, PathBuf>>,
+}
+
+impl FileWriter {
+    fn new(options: &UnrollOptions) -> Self {
+        Self {
+            workers: Workers::for_options(options),
+            update: flag!(options.flags[DIFF_UPDATE]),
+            duplicates: flag!(options.flags[DEDUP_FILES]).then(HashMap::new),
+        }
+    }
+
+    /// Unpack file entry
+    ///
+    /// The file is written by workers when it's enabled
+    /// or linked to the already extracted duplicate when it's found.
+    /// Returns the size of file contents or `None` when it's skipped.
+    fn unpack<R>(
+        &mut self,
+        entry: &mut TarEntry<R>,
+        entry_path: &Path,
+        path: &Path,
+        options: &UnrollOptions,
+    ) -> Result<Option<u64>>
+    where
+        R: Read,
+    {
+        if self.workers.is_none()
+            && self.duplicates.is_none()
+            && !self.update
+            && options.map_contents.is_none()
+            && options.exec_patterns.is_empty()
+            && !flag!(options.flags[DETECT_EXECUTABLES])
+        {
+            #[cfg(feature = "mmap")]
+            if options
+                .mmap_threshold
+                .is_some_and(|threshold| entry.size() >= threshold)
+                && entry.header().entry_type() == TarEntryType::Regular
+            {
+                FileJob::new(entry, path, Vec::new(), options).write_mapped(entry)?;
+                return Ok(Some(entry.size()));
+            }
+            entry.unpack(path)?;
+            apply_ownership(path, options.ownership)?;
+            return Ok(Some(entry.size()));
+        }
+
+        let Some(data) = entry_contents(entry, entry_path, options)? else {
+            return Ok(None);
+        };
+
+        let mut job = FileJob::new(entry, path, data, options);
+        let size = job.data.len() as u64;
+
+        if is_executable(entry_path, &job.data, options) {
+            job.mode = Some(job.mode.unwrap_or(0o644) | 0o111);
+        }
+
+        self.write(job)?;
+
+        Ok(Some(size))
+    }
+
+    /// Write file contents
+    fn write(&mut self, job: FileJob) -> Status {
+        let path = job.path.as_path();
+
+        if self.update {
+            if let Some(workers) = &mut self.workers {
+                if workers.pending_paths.contains(&job.path) {
+                    workers.wait()?;
+                }
+            }
+            if has_contents(&job.path, &job.data)? {
+                return Ok(());
+            }
+        }
+
+        if let Some(duplicates) = &mut self.duplicates {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(&job.data);
+            let key = (job.data.len() as u64, job.mode, hasher.finish());
+
+            match duplicates.entry(key) {
+                MapEntry::Occupied(original) => {
+                    if let Some(workers) = &mut self.workers {
+                        workers.wait()?;
+                    }
+                    // Original file may be overwritten since
+                    if std::fs::read(original.get())? == job.data {
+                        if path.symlink_metadata().is_ok() {
+                            remove_file(path).context("remove file", path)?;
+                        }
+                        hard_link(original.get(), path).context("create link", path)?;
+                        return Ok(());
+                    }
+                }
+                MapEntry::Vacant(entry) => {
+                    entry.insert(job.path.clone());
+                }
+            }
+        }
+
+        if let Some(workers) = &mut self.workers {
+            workers.submit(job)
+        } else {
+            Ok(job.write()?)
+        }
+    }
+
+    /// Wait until all files will be written
+    fn wait(&mut self) -> Status {
+        self.workers.as_mut().map_or(Ok(()), Workers::wait)
+    }
+}
+
+/// Read contents of file entry mapping it when required
+///
+/// Returns `None` when entry should be skipped.
+fn entry_contents<R>(
+    entry: &mut TarEntry<R>,
+    entry_path: &Path,
+    options: &UnrollOptions,
+) -> Result<Option<Vec<u8>>>
+where
+    R: Read,
+{
+    if let Some(map_contents) = &options.map_contents {
+        return map_contents(entry_path, entry);
+    }
+
+    let mut data = Vec::with_capacity(usize::try_from(entry.size()).unwrap_or_default());
+    entry.read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Check whether file should be executable according to options
+fn is_executable(entry_path: &Path, data: &[u8], options: &UnrollOptions) -> bool {
+    const MAGICS: &[&[u8]] = &[
+        // ELF
+        b"\x7fELF",
+        // Mach-O (32/64-bit, both endians)
+        b"\xfe\xed\xfa\xce",
+        b"\xfe\xed\xfa\xcf",
+        b"\xce\xfa\xed\xfe",
+        b"\xcf\xfa\xed\xfe",
+        // Mach-O universal
+        b"\xca\xfe\xba\xbe",
+    ];
+
+    matches_patterns(&options.exec_patterns, entry_path)
+        || (flag!(options.flags[DETECT_EXECUTABLES])
+            && MAGICS.iter().any(|magic| data.starts_with(magic)))
+}
+
+/// Check that relative path matches some of wildcard patterns
+///
+/// Patterns without `/` matches file name, other patterns matches whole path.
+pub fn matches_patterns(patterns: &[String], path: &Path) -> bool {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let name = path.rsplit('/').next().unwrap_or_default();
+
+    patterns
+        .iter()
+        .any(|pattern| wildcard_match(pattern, if pattern.contains('/') { &path } else { name }))
+}
+
+/// Check that file exists and has the specified contents
+fn has_contents(path: &Path, data: &[u8]) -> StdResult<bool, IoError> {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_file() && metadata.len() == data.len() as u64 => {
+            Ok(std::fs::read(path)? == data)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// File which should be written by workers
+struct FileJob {
+    path: PathBuf,
+    data: Vec<u8>,
+    mode: Option<u32>,
+    mtime: Option<u64>,
+    ownership: Ownership,
+    /// Ids of owner from archive (when it should be applied)
+    owner: Option<(u32, u32)>,
+}
+
+impl FileJob {
+    fn new<R>(entry: &TarEntry<R>, path: &Path, data: Vec<u8>, options: &UnrollOptions) -> Self
+    where
+        R: Read,
+    {
+        Self {
+            path: path.into(),
+            data,
+            mode: entry.header().mode().ok(),
+            mtime: entry.header().mtime().ok(),
+            ownership: options.ownership,
+            owner: entry_owner(entry, options.ownership),
+        }
+    }
+
+    fn write(&self) -> StdResult<(), IoError> {
+        // Do not follow symlinks which may be placed here before
+        if self.path.symlink_metadata().is_ok() {
+            remove_file(&self.path).context("remove file", &self.path)?;
+        }
+
+        let mut file = File::create(&self.path).context("create file", &self.path)?;
+        file.write_all(&self.data)
+            .context("write file", &self.path)?;
+
+        self.finish(&file)
+    }
+
+    /// Write contents of entry into pre-allocated memory-mapped file
+    #[cfg(feature = "mmap")]
+    fn write_mapped<R>(&self, entry: &mut TarEntry<R>) -> StdResult<(), IoError>
+    where
+        R: Read,
+    {
+        if self.path.symlink_metadata().is_ok() {
+            remove_file(&self.path).context("remove file", &self.path)?;
+        }
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)
+            .context("create file", &self.path)?;
+        file.set_len(entry.size())
+            .context("allocate file", &self.path)?;
+
+        if entry.size() > 0 {
+            // File is just created and not changed by anyone else while mapped
+            let mut map =
+                unsafe { memmap2::MmapMut::map_mut(&file) }.context("map file", &self.path)?;
+            entry
+                .read_exact(&mut map)
+                .context("write file", &self.path)?;
+            map.flush().context("write file", &self.path)?;
+        }
+
+        self.finish(&file)
+    }
+
+    /// Apply attributes to written file
+    fn finish(&self, file: &File) -> StdResult<(), IoError> {
+        if let Some(mtime) = self.mtime {
+            // Zero mtime is avoided like `tar` does
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime.max(1)))
+                .context("set modification time of", &self.path)?;
+        }
+
+        if let Some(mode) = self.mode {
+            set_file_mode(file, mode).context("set permissions of", &self.path)?;
+        }
+
+        if let Some(owner) = self.owner {
+            apply_owner(&self.path, owner).context("change owner of", &self.path)?;
+        }
+
+        apply_ownership(&self.path, self.ownership).map_err(|error| match error {
+            Error::Io(error) => error,
+            error => IoError::other(ErrorChain(&error).to_string()),
+        })
+    }
+}
+
+/// Get ids of entry owner when it should be applied to extracted file
+fn entry_owner<R>(entry: &TarEntry<R>, ownership: Ownership) -> Option<(u32, u32)>
+where
+    R: Read,
+{
+    if ownership != Ownership::Privileged || !is_privileged() {
+        return None;
+    }
+    let header = entry.header();
+    let uid = u32::try_from(header.uid().ok()?).ok()?;
+    let gid = u32::try_from(header.gid().ok()?).ok()?;
+    Some((uid, gid))
+}
+
+#[cfg(unix)]
+fn apply_owner(path: &Path, (uid, gid): (u32, u32)) -> StdResult<(), IoError> {
+    lchown(path, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn apply_owner(_path: &Path, _owner: (u32, u32)) -> StdResult<(), IoError> {
+    Ok(())
+}
+
+/// Pool of threads which writes extracted files
+struct Workers {
+    sender: Option<SyncSender<FileJob>>,
+    threads: Vec<JoinHandle<()>>,
+    state: Arc<WorkersState>,
+    /// Paths of files submitted since last waiting
+    pending_paths: HashSet<PathBuf>,
+}
+
+#[derive(Default)]
+struct WorkersState {
+    /// Number of unfinished jobs and the first error occurred
+    status: Mutex<(usize, Option<IoError>)>,
+    finished: Condvar,
+}
+
+impl WorkersState {
+    fn finish(&self, result: StdResult<(), IoError>) {
+        {
+            let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+            status.0 -= 1;
+            if let Err(error) = result {
+                status.1.get_or_insert(error);
+            }
+        }
+        self.finished.notify_all();
+    }
+}
+
+impl Workers {
+    /// Create pool when parallel writing is enabled by options
+    fn for_options(options: &UnrollOptions) -> Option<Self> {
+        (options.threads > 1
+            && !flag!(options.flags[RESUMABLE_UNROLL])
+            && !flag!(options.flags[REPRODUCIBLE])
+            && !flag!(options.flags[UNPACK_XATTRS])
+            // Threads may be not supported by sandboxed WASI runners
+            && cfg!(not(target_family = "wasm")))
+        .then(|| Self::new(options.threads, options.cancel.as_ref()))
+    }
+
+    fn new(num_of_threads: usize, cancel: Option<&CancelToken>) -> Self {
+        let (sender, receiver) = sync_channel::<FileJob>(num_of_threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let state = Arc::new(WorkersState::default());
+
+        let threads = (0..num_of_threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let state = state.clone();
+                let cancel = cancel.cloned();
+
+                spawn(move || loop {
+                    let job = receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+
+                    state.finish(if cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                        Err(IoError::other(Cancelled))
+                    } else {
+                        job.write()
+                    });
+                })
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            threads,
+            state,
+            pending_paths: HashSet::new(),
+        }
+    }
+
+    /// Queue file for writing
+    fn submit(&mut self, job: FileJob) -> Status {
+        // The same file should not be written concurrently
+        if self.pending_paths.contains(&job.path) {
+            self.wait()?;
+        }
+        self.pending_paths.insert(job.path.clone());
+
+        self.state
+            .status
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .0 += 1;
+
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(job).ok())
+            .ok_or_else(|| IoError::other("Writing threads terminated").into())
+    }
+
+    /// Wait until all queued files will be written
+    fn wait(&mut self) -> Status {
+        let mut status = self
+            .state
+            .status
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while status.0 > 0 {
+            status = self
+                .state
+                .finished
+                .wait(status)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        self.pending_paths.clear();
+
+        status.1.take().map_or(Ok(()), |error| Err(error.into()))
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        // Threads exits when channel is closed
+        self.sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Archive scanning results
+pub struct ArchiveScan {
+    /// Number of common leading components
+    pub common_components: usize,
+    /// Sole top-level directory
+    pub toplevel: Option<PathBuf>,
+    /// Number of entries
+    pub total_entries: usize,
+    /// Total size of files
+    pub total_size: u64,
+}
+
+/// Scan archive to get the common leading components and the number of entries
+pub fn scan_archive<R>(archive: &mut TarArchive<R>) -> StdResult<ArchiveScan, IoError>
+where
+    R: Read,
+{
+    let mut common_ancestor: Option<PathBuf> = None;
+    // Outer `None` means that no entries was seen yet
+    let mut toplevel: Option<Option<PathBuf>> = None;
+    let mut total_entries = 0;
+    let mut total_size = 0u64;
+
+    for entry in archive.entries().map_err(decode_error)? {
+        let entry = entry.map_err(decode_error)?;
+        total_entries += 1;
+        if entry.header().entry_type().is_file() {
+            total_size = total_size.saturating_add(entry.size());
+        }
+        let entry_path = PathBuf::from(String::from_utf8_lossy(&entry.path_bytes()).as_ref());
+
+        match entry.header().entry_type() {
+            TarEntryType::Directory
+            | TarEntryType::Regular
+            | TarEntryType::Continuous
+            | TarEntryType::GNUSparse => {
+                let sanitized_path = entry_path_stripped(&entry_path, 0).unwrap_or_default();
+                let mut components = sanitized_path.iter().map(PathBuf::from);
+                let first = components.next();
+                // Files on top-level are not allowed
+                let is_nested = components.next().is_some() || entry.header().entry_type().is_dir();
+                let candidate = first.filter(|_| is_nested);
+
+                toplevel = Some(match toplevel {
+                    Some(toplevel) => {
+                        toplevel.filter(|toplevel| Some(toplevel) == candidate.as_ref())
+                    }
+                    None => candidate,
+                });
+
+                if let Some(common_ancestor) = common_ancestor.as_mut() {
+                    *common_ancestor = common_ancestor
+                        .iter()
+                        .zip(entry_path.iter())
+                        .take_while(|(common_component, entry_component)| {
+                            common_component == entry_component
+                        })
+                        .map(|(common_component, _)| common_component)
+                        .collect();
+                } else {
+                    common_ancestor = Some(entry_path);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(ArchiveScan {
+        common_components: common_ancestor.map_or(0, |path| path.iter().count()),
+        toplevel: toplevel.flatten(),
+        total_entries,
+        total_size,
+    })
+}
+
+/// Check that destination has enough free space for files of specified size
+///
+/// The space is determined for the nearest existing ancestor when destination doesn't exist.
+pub fn check_free_space(path: &Path, required: Option<u64>) -> Status {
+    let Some(required) = required else {
+        return Ok(());
+    };
+    // Unknown space is not a reason to fail
+    let Some(available) = path.ancestors().find_map(available_space) else {
+        return Ok(());
+    };
+    if required > available {
+        return Err(Error::NoSpace {
+            path: path.into(),
+            required,
+            available,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is nul-terminated and `stat` is initialized on success
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: `statvfs` succeeded
+    let stat = unsafe { stat.assume_init() };
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+const fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn remove_dir_entries(path: &Path) -> StdResult<(), IoError> {
+    for entry in path.read_dir().context("read directory", path)? {
+        let path = entry?.path();
+        if path.is_file() {
+            remove_file(&path).context("remove file", &path)?;
+        } else {
+            remove_dir_all(&path).context("remove directory", &path)?;
+        }
+    }
+    Ok(())
+}